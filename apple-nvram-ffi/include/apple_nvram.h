@@ -0,0 +1,54 @@
+/* SPDX-License-Identifier: MIT */
+/*
+ * C interface to apple-nvram. Link with -lapple_nvram.
+ *
+ * Sections are named "common" or "system". Values are raw bytes, not the
+ * %xx-escaped text the asahi-nvram tool prints. Functions returning int
+ * return one of the APPLE_NVRAM_* codes below.
+ */
+#ifndef APPLE_NVRAM_H
+#define APPLE_NVRAM_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define APPLE_NVRAM_OK 0
+#define APPLE_NVRAM_PARSE 1
+#define APPLE_NVRAM_NOT_FOUND 2
+#define APPLE_NVRAM_INVALID 3
+#define APPLE_NVRAM_TOO_SMALL 4
+#define APPLE_NVRAM_SECTION_TOO_BIG 5
+
+typedef struct apple_nvram apple_nvram;
+
+/* Parses a copy of an image, both banks. Returns NULL if it is not one. */
+apple_nvram *apple_nvram_parse(const uint8_t *data, size_t len);
+void apple_nvram_free(apple_nvram *nv);
+
+/*
+ * *len is the size of buf on entry and the size of the value on return.
+ * Returns APPLE_NVRAM_TOO_SMALL, copying nothing, if buf is too small.
+ */
+int apple_nvram_get(apple_nvram *nv, const char *section, const char *name,
+                    uint8_t *buf, size_t *len);
+int apple_nvram_set(apple_nvram *nv, const char *section, const char *name,
+                    const uint8_t *value, size_t len);
+int apple_nvram_delete(apple_nvram *nv, const char *section, const char *name);
+
+/*
+ * The image to write back. The first set or delete on a handle moves it to
+ * a new generation in the other bank, so only that bank differs.
+ * Release *out with apple_nvram_free_buffer().
+ */
+int apple_nvram_serialize(const apple_nvram *nv, uint8_t **out, size_t *out_len);
+void apple_nvram_free_buffer(uint8_t *buf, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* APPLE_NVRAM_H */
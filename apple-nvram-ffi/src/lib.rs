@@ -0,0 +1,247 @@
+// SPDX-License-Identifier: MIT
+//! A C ABI for apple-nvram, declared in `include/apple_nvram.h`.
+//!
+//! A handle owns a copy of the image it was parsed from. Values cross the
+//! boundary raw, never `%xx`-escaped. The first change to a handle stages a
+//! new generation in the other bank, as the tools do before writing.
+//!
+//! The header is kept by hand; `tests/c.rs` checks it against the exports
+//! and builds a C program with it.
+#![allow(clippy::missing_safety_doc)]
+
+use std::{
+    ffi::{c_char, c_int, CStr},
+    ptr, slice,
+};
+
+use apple_nvram::{Nvram, Section, Variable};
+
+pub const APPLE_NVRAM_OK: c_int = 0;
+pub const APPLE_NVRAM_PARSE: c_int = 1;
+pub const APPLE_NVRAM_NOT_FOUND: c_int = 2;
+pub const APPLE_NVRAM_INVALID: c_int = 3;
+pub const APPLE_NVRAM_TOO_SMALL: c_int = 4;
+pub const APPLE_NVRAM_SECTION_TOO_BIG: c_int = 5;
+
+pub struct Handle {
+    // Borrows from the image and `keys`, so it is declared first to drop first.
+    nv: Nvram<'static>,
+    keys: Vec<Box<[u8]>>,
+    _data: Box<[u8]>,
+    dirty: bool,
+}
+
+// Extends the lifetime of bytes owned by a handle. They are boxed, so they
+// stay put when the handle moves, and outlive `nv`.
+unsafe fn owned(bytes: &[u8]) -> &'static [u8] {
+    slice::from_raw_parts(bytes.as_ptr(), bytes.len())
+}
+
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> &'a [u8] {
+    match len {
+        0 => &[],
+        _ => slice::from_raw_parts(data, len),
+    }
+}
+
+unsafe fn section<'a>(
+    nv: &'a mut Nvram<'static>,
+    name: *const c_char,
+) -> Option<&'a mut Section<'static>> {
+    if name.is_null() {
+        return None;
+    }
+    let part = nv.active_part_mut();
+    match CStr::from_ptr(name).to_bytes() {
+        b"common" => Some(&mut part.common),
+        b"system" => Some(&mut part.system),
+        _ => None,
+    }
+}
+
+unsafe fn key<'a>(name: *const c_char) -> Option<&'a [u8]> {
+    (!name.is_null()).then(|| CStr::from_ptr(name).to_bytes())
+}
+
+/// Parses a copy of `len` bytes at `data`. Returns NULL if they do not hold
+/// a store.
+#[no_mangle]
+pub unsafe extern "C" fn apple_nvram_parse(data: *const u8, len: usize) -> *mut Handle {
+    if data.is_null() {
+        return ptr::null_mut();
+    }
+    let data: Box<[u8]> = bytes(data, len).into();
+    let Ok(nv) = Nvram::parse(owned(&data)) else {
+        return ptr::null_mut();
+    };
+    Box::into_raw(Box::new(Handle {
+        nv,
+        keys: Vec::new(),
+        _data: data,
+        dirty: false,
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn apple_nvram_free(nv: *mut Handle) {
+    if !nv.is_null() {
+        drop(Box::from_raw(nv));
+    }
+}
+
+/// Copies the value of `name` in `section` ("common" or "system") to `buf`.
+/// `*len` is the size of `buf` on entry and the size of the value on return;
+/// if `buf` is too small nothing is copied and APPLE_NVRAM_TOO_SMALL returned.
+#[no_mangle]
+pub unsafe extern "C" fn apple_nvram_get(
+    nv: *mut Handle,
+    section_name: *const c_char,
+    name: *const c_char,
+    buf: *mut u8,
+    len: *mut usize,
+) -> c_int {
+    let (Some(nv), false) = (nv.as_mut(), len.is_null()) else {
+        return APPLE_NVRAM_INVALID;
+    };
+    let (Some(section), Some(key)) = (section(&mut nv.nv, section_name), key(name)) else {
+        return APPLE_NVRAM_INVALID;
+    };
+    let Some(var) = section.values.get(key) else {
+        return APPLE_NVRAM_NOT_FOUND;
+    };
+    let value = var.bytes();
+    let room = *len;
+    *len = value.len();
+    if room < value.len() || (buf.is_null() && !value.is_empty()) {
+        return APPLE_NVRAM_TOO_SMALL;
+    }
+    if !value.is_empty() {
+        ptr::copy_nonoverlapping(value.as_ptr(), buf, value.len());
+    }
+    APPLE_NVRAM_OK
+}
+
+/// Sets `name` in `section` to the `len` bytes at `value`.
+#[no_mangle]
+pub unsafe extern "C" fn apple_nvram_set(
+    nv: *mut Handle,
+    section_name: *const c_char,
+    name: *const c_char,
+    value: *const u8,
+    len: usize,
+) -> c_int {
+    let Some(nv) = nv.as_mut() else {
+        return APPLE_NVRAM_INVALID;
+    };
+    let Some(name) = key(name) else {
+        return APPLE_NVRAM_INVALID;
+    };
+    if name.is_empty() || name.contains(&b'=') || (value.is_null() && len != 0) {
+        return APPLE_NVRAM_INVALID;
+    }
+    if section(&mut nv.nv, section_name).is_none() {
+        return APPLE_NVRAM_INVALID;
+    }
+    if !nv.dirty {
+        nv.nv.prepare_for_write();
+        nv.dirty = true;
+    }
+    let values = &mut section(&mut nv.nv, section_name).unwrap().values;
+    // Reuse the name already in the section or kept by an earlier set, so
+    // setting the same variable over and over does not grow the handle.
+    let key = match values.get_key_value(name) {
+        Some((&key, _)) => key,
+        None => match nv.keys.iter().find(|k| ***k == *name) {
+            Some(kept) => owned(kept),
+            None => {
+                nv.keys.push(name.into());
+                owned(nv.keys.last().unwrap())
+            }
+        },
+    };
+    values.insert(key, Variable::from_bytes(key, bytes(value, len)));
+    APPLE_NVRAM_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn apple_nvram_delete(
+    nv: *mut Handle,
+    section_name: *const c_char,
+    name: *const c_char,
+) -> c_int {
+    let Some(nv) = nv.as_mut() else {
+        return APPLE_NVRAM_INVALID;
+    };
+    let (Some(found), Some(key)) = (section(&mut nv.nv, section_name), key(name)) else {
+        return APPLE_NVRAM_INVALID;
+    };
+    if !found.values.contains_key(key) {
+        return APPLE_NVRAM_NOT_FOUND;
+    }
+    if !nv.dirty {
+        nv.nv.prepare_for_write();
+        nv.dirty = true;
+    }
+    section(&mut nv.nv, section_name)
+        .unwrap()
+        .values
+        .shift_remove(key);
+    APPLE_NVRAM_OK
+}
+
+/// Serializes the store, both banks, into a buffer to be released with
+/// apple_nvram_free_buffer().
+#[no_mangle]
+pub unsafe extern "C" fn apple_nvram_serialize(
+    nv: *const Handle,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    let (Some(nv), false, false) = (nv.as_ref(), out.is_null(), out_len.is_null()) else {
+        return APPLE_NVRAM_INVALID;
+    };
+    let data = match nv.nv.serialize() {
+        Ok(data) => data.into_boxed_slice(),
+        Err(apple_nvram::Error::SectionTooBig) => return APPLE_NVRAM_SECTION_TOO_BIG,
+        Err(_) => return APPLE_NVRAM_PARSE,
+    };
+    *out_len = data.len();
+    *out = Box::into_raw(data) as *mut u8;
+    APPLE_NVRAM_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn apple_nvram_free_buffer(buf: *mut u8, len: usize) {
+    if !buf.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(buf, len)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_reuses_names() {
+        let image = apple_nvram::MemoryNvram::with_vars(&[], &[("auto-boot", b"true")]).unwrap();
+        let (system, name, existing) = (c"system".as_ptr(), c"x".as_ptr(), c"auto-boot".as_ptr());
+        unsafe {
+            let nv = apple_nvram_parse(image.data.as_ptr(), image.data.len());
+            for value in 0..10u8 {
+                assert_eq!(apple_nvram_set(nv, system, name, &value, 1), APPLE_NVRAM_OK);
+                assert_eq!(
+                    apple_nvram_set(nv, system, existing, &value, 1),
+                    APPLE_NVRAM_OK
+                );
+            }
+            assert_eq!(apple_nvram_delete(nv, system, name), APPLE_NVRAM_OK);
+            assert_eq!(
+                apple_nvram_set(nv, system, name, ptr::null(), 0),
+                APPLE_NVRAM_OK
+            );
+            // auto-boot keeps the name parsed from the image.
+            assert_eq!((*nv).keys.len(), 1);
+            apple_nvram_free(nv);
+        }
+    }
+}
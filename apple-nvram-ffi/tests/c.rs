@@ -0,0 +1,87 @@
+// SPDX-License-Identifier: MIT
+//! Checks the C header against the library: the declarations match what is
+//! exported, and a C program compiles, links and runs against the static
+//! library. Needs a C compiler, `cc` or whatever `CC` names.
+
+use std::{env, fs, path::PathBuf, process::Command};
+
+use apple_nvram::MemoryNvram;
+
+const HEADER: &str = include_str!("../include/apple_nvram.h");
+const SOURCE: &str = include_str!("../src/lib.rs");
+
+// The names following each `prefix` in `text`, up to a character that
+// cannot be part of one.
+fn names<'a>(text: &'a str, prefix: &str) -> Vec<&'a str> {
+    let mut ret = text
+        .split(prefix)
+        .skip(1)
+        .map(|rest| {
+            let end = rest
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .unwrap_or(rest.len());
+            &rest[..end]
+        })
+        .collect::<Vec<_>>();
+    ret.sort();
+    ret.dedup();
+    ret
+}
+
+#[test]
+fn header_declares_every_export() {
+    let declared = names(HEADER, "apple_nvram_")
+        .into_iter()
+        .filter(|n| HEADER.contains(&format!("apple_nvram_{n}(")))
+        .map(|n| format!("apple_nvram_{n}"))
+        .collect::<Vec<_>>();
+    assert_eq!(names(SOURCE, "extern \"C\" fn "), declared);
+}
+
+#[test]
+fn header_has_the_same_codes() {
+    let rust = SOURCE
+        .lines()
+        .filter_map(|l| l.strip_prefix("pub const "))
+        .map(|l| l.replace(": c_int = ", " ").replace(';', ""))
+        .collect::<Vec<_>>();
+    let c = HEADER
+        .lines()
+        .filter_map(|l| l.strip_prefix("#define APPLE_NVRAM_"))
+        .filter(|l| l.contains(' '))
+        .map(|l| format!("APPLE_NVRAM_{l}"))
+        .collect::<Vec<_>>();
+    assert!(!rust.is_empty());
+    assert_eq!(rust, c);
+}
+
+#[test]
+fn c_program_links_and_runs() {
+    let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    // cargo builds the static library next to the test binary.
+    let lib = env::current_exe()
+        .unwrap()
+        .with_file_name("libapple_nvram.a");
+    assert!(lib.exists(), "{} was not built", lib.display());
+    let dir = env::temp_dir().join(format!("apple-nvram-ffi-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let image = MemoryNvram::with_vars(&[], &[("auto-boot", b"true")]).unwrap();
+    fs::write(dir.join("nvram.img"), &image.data).unwrap();
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_owned());
+    let status = Command::new(cc)
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror", "-I"])
+        .arg(root.join("include"))
+        .arg(root.join("tests/smoke.c"))
+        .arg(&lib)
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(dir.join("smoke"))
+        .status()
+        .expect("could not run the C compiler");
+    assert!(status.success(), "smoke.c did not build");
+    let status = Command::new(dir.join("smoke"))
+        .arg(dir.join("nvram.img"))
+        .status()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    assert!(status.success(), "smoke test failed");
+}
@@ -0,0 +1,70 @@
+/* SPDX-License-Identifier: MIT */
+/* Built and run by tests/c.rs against the static library. */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "apple_nvram.h"
+
+#define CHECK(cond)                                                        \
+    do {                                                                   \
+        if (!(cond)) {                                                     \
+            fprintf(stderr, "%s:%d: %s\n", __FILE__, __LINE__, #cond);     \
+            return 1;                                                      \
+        }                                                                  \
+    } while (0)
+
+static int read_file(const char *path, uint8_t **data, size_t *len)
+{
+    FILE *f = fopen(path, "rb");
+    if (!f)
+        return -1;
+    fseek(f, 0, SEEK_END);
+    *len = (size_t)ftell(f);
+    rewind(f);
+    *data = malloc(*len);
+    size_t got = fread(*data, 1, *len, f);
+    fclose(f);
+    return got == *len ? 0 : -1;
+}
+
+int main(int argc, char **argv)
+{
+    uint8_t *data, *out;
+    size_t len, out_len;
+    uint8_t buf[64];
+    size_t buf_len = sizeof(buf);
+
+    CHECK(argc == 2);
+    CHECK(read_file(argv[1], &data, &len) == 0);
+    CHECK(apple_nvram_parse(data, 16) == NULL);
+
+    apple_nvram *nv = apple_nvram_parse(data, len);
+    CHECK(nv != NULL);
+    CHECK(apple_nvram_get(nv, "system", "auto-boot", buf, &buf_len) == APPLE_NVRAM_OK);
+    CHECK(buf_len == 4 && memcmp(buf, "true", 4) == 0);
+    buf_len = 2;
+    CHECK(apple_nvram_get(nv, "system", "auto-boot", buf, &buf_len) == APPLE_NVRAM_TOO_SMALL);
+    CHECK(buf_len == 4);
+    CHECK(apple_nvram_get(nv, "bogus", "auto-boot", buf, &buf_len) == APPLE_NVRAM_INVALID);
+    CHECK(apple_nvram_get(nv, "common", "nope", buf, &buf_len) == APPLE_NVRAM_NOT_FOUND);
+
+    /* A value with NULs, which the store escapes. */
+    const uint8_t value[] = {'a', 0, 0, 0xff, 'b'};
+    CHECK(apple_nvram_set(nv, "common", "test", value, sizeof(value)) == APPLE_NVRAM_OK);
+    CHECK(apple_nvram_delete(nv, "system", "auto-boot") == APPLE_NVRAM_OK);
+    CHECK(apple_nvram_serialize(nv, &out, &out_len) == APPLE_NVRAM_OK);
+    CHECK(out_len == len);
+    apple_nvram_free(nv);
+
+    nv = apple_nvram_parse(out, out_len);
+    apple_nvram_free_buffer(out, out_len);
+    CHECK(nv != NULL);
+    buf_len = sizeof(buf);
+    CHECK(apple_nvram_get(nv, "common", "test", buf, &buf_len) == APPLE_NVRAM_OK);
+    CHECK(buf_len == sizeof(value) && memcmp(buf, value, sizeof(value)) == 0);
+    CHECK(apple_nvram_get(nv, "system", "auto-boot", buf, &buf_len) == APPLE_NVRAM_NOT_FOUND);
+    apple_nvram_free(nv);
+    free(data);
+    return 0;
+}
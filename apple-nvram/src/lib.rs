@@ -1,11 +1,59 @@
 // SPDX-License-Identifier: MIT
-use std::{
-    borrow::Cow,
-    collections::HashMap,
-    fmt::{Debug, Formatter},
-    fs::File,
-    os::unix::io::AsRawFd,
-};
+//! Parsing and writing the nvram format. Without the default `std` feature
+//! only this part is built, on `core` and `alloc`, for environments such as
+//! bootloaders; the stores and MTD support need `std`.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+use alloc::{borrow::Cow, string::String, vec::Vec};
+use core::fmt::{Debug, Formatter};
+
+use indexmap::IndexMap;
+use tracing::{debug, trace};
+
+#[cfg(feature = "std")]
+mod mtd;
+#[cfg(feature = "std")]
+pub mod store;
+
+#[cfg(feature = "std")]
+pub use mtd::{erase_if_needed, erase_range, erase_size, find_mtd, EraseInfoUser, MtdInfoUser};
+#[cfg(feature = "std")]
+pub use store::{MappedStore, MemoryNvram, NvramStore};
+
+/// Hashes the variable maps. std's randomly seeded hasher is not available
+/// without it, there FNV-1a stands in.
+#[cfg(feature = "std")]
+pub type VarHasher = std::collections::hash_map::RandomState;
+#[cfg(not(feature = "std"))]
+pub type VarHasher = core::hash::BuildHasherDefault<Fnv>;
+
+/// The variables of a section, in the order they are stored.
+pub type VarMap<'a> = IndexMap<&'a [u8], Variable<'a>, VarHasher>;
+
+#[cfg(not(feature = "std"))]
+pub struct Fnv(u64);
+
+#[cfg(not(feature = "std"))]
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf29ce484222325)
+    }
+}
+
+#[cfg(not(feature = "std"))]
+impl core::hash::Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+}
 
 pub struct UnescapeVal<I> {
     inner: I,
@@ -50,6 +98,46 @@ where
     }
 }
 
+pub struct EscapeVal<I: Iterator<Item = u8>> {
+    inner: core::iter::Peekable<I>,
+    pending: Option<u8>,
+}
+
+impl<I> EscapeVal<I>
+where
+    I: Iterator<Item = u8>,
+{
+    pub fn new(inner: I) -> Self {
+        Self {
+            inner: inner.peekable(),
+            pending: None,
+        }
+    }
+}
+
+impl<I> Iterator for EscapeVal<I>
+where
+    I: Iterator<Item = u8>,
+{
+    type Item = u8;
+    fn next(&mut self) -> Option<u8> {
+        if let Some(p) = self.pending.take() {
+            return Some(p);
+        }
+        let n = self.inner.next()?;
+        if n != 0 && n != 0xFF {
+            return Some(n);
+        }
+        let mut count = 1;
+        while count < 0x7F && self.inner.peek() == Some(&n) {
+            self.inner.next();
+            count += 1;
+        }
+        self.pending = Some(if n == 0 { count } else { count | 0x80 });
+        Some(0xFF)
+    }
+}
+
 #[derive(Clone)]
 pub struct CHRPHeader<'a> {
     pub name: &'a [u8],
@@ -58,7 +146,7 @@ pub struct CHRPHeader<'a> {
 }
 
 impl Debug for CHRPHeader<'_> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("CHRPHeader")
             .field("name", &String::from_utf8_lossy(self.name).into_owned())
             .field("size", &self.size)
@@ -76,6 +164,16 @@ fn chrp_checksum_add(lhs: u8, rhs: u8) -> u8 {
     }
 }
 
+// Every integer on flash is little-endian, whatever the host is. All reads
+// go through these so that there is exactly one place to get it right.
+fn le_u16(nvr: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([nvr[at], nvr[at + 1]])
+}
+
+fn le_u32(nvr: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(nvr[at..at + 4].try_into().unwrap())
+}
+
 fn slice_rstrip<'a, T: PartialEq<T>>(mut ts: &'a [T], t: &T) -> &'a [T] {
     while let Some(last) = ts.last() {
         if last == t {
@@ -102,15 +200,78 @@ fn slice_find<T: PartialEq<T>>(ts: &[T], t: &T) -> Option<usize> {
 pub enum Error {
     ParseError,
     SectionTooBig,
+    /// The store is in a layout that is recognised but not supported.
+    UnsupportedVersion(u8),
+    /// Reading or writing a [`NvramStore`] failed.
+    #[cfg(feature = "std")]
+    IoError(std::io::Error),
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::ParseError => f.write_str("not a valid nvram store"),
+            Error::SectionTooBig => f.write_str("section does not fit in its partition"),
+            Error::UnsupportedVersion(n) => write!(f, "unsupported nvram format version {n}"),
+            #[cfg(feature = "std")]
+            Error::IoError(e) => write!(f, "{e}"),
+        }
+    }
 }
 
-type Result<T> = std::result::Result<T, Error>;
+/// On-flash layouts, told apart by their first bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Two banks, each an `nvram` CHRP partition holding `common` and
+    /// `system`: version 1 and 2 stores of ARM Macs.
+    Chrp,
+    /// A single run of CHRP partitions, as in other Open Firmware stores
+    /// and images taken from older Macs.
+    Flat,
+    /// The variable store of newer firmware, with an `NVV3` header. Not
+    /// supported.
+    V3,
+}
+
+impl Format {
+    /// The layout `nvr` is in, or `None` if it is not one of the known ones.
+    pub fn detect(nvr: &[u8]) -> Option<Format> {
+        match nvr.get(..4)? {
+            // The signature is a multi-character constant, stored
+            // little-endian on flash.
+            b"3VVN" | b"NVV3" => Some(Format::V3),
+            _ => match CHRPHeader::parse(nvr).ok()?.name {
+                b"nvram" => Some(Format::Chrp),
+                _ => Some(Format::Flat),
+            },
+        }
+    }
+}
+
+/// Where [`Nvram::serialize`] puts the active partition.
+#[derive(Debug, Clone)]
+pub enum Layout<'a> {
+    /// Both banks, as parsed from a [`Format::Chrp`] store.
+    Banked,
+    /// Back into the slots of `common` and `system` in `image`, a
+    /// [`Format::Flat`] store, leaving its other partitions alone.
+    Flat {
+        image: &'a [u8],
+        common_at: usize,
+        system_at: Option<usize>,
+    },
+}
+
+type Result<T> = core::result::Result<T, Error>;
 
 impl CHRPHeader<'_> {
     pub fn parse(nvr: &[u8]) -> Result<CHRPHeader<'_>> {
+        if nvr.len() < 16 {
+            return Err(Error::ParseError);
+        }
         let signature = nvr[0];
         let cksum = nvr[1];
-        let size = u16::from_le_bytes(nvr[2..4].try_into().unwrap());
+        let size = le_u16(nvr, 2);
         let name = slice_rstrip(&nvr[4..16], &0);
         let cand = CHRPHeader {
             name,
@@ -118,6 +279,12 @@ impl CHRPHeader<'_> {
             signature,
         };
         if cand.checksum() != cksum {
+            debug!(
+                ?cand,
+                stored = cksum,
+                computed = cand.checksum(),
+                "header checksum mismatch"
+            );
             return Err(Error::ParseError);
         }
         Ok(cand)
@@ -128,8 +295,9 @@ impl CHRPHeader<'_> {
             cksum = chrp_checksum_add(cksum, u);
         }
         cksum = chrp_checksum_add(cksum, self.signature);
-        cksum = chrp_checksum_add(cksum, (self.size & 0xFF) as u8);
-        chrp_checksum_add(cksum, (self.size >> 8) as u8)
+        let [lo, hi] = self.size.to_le_bytes();
+        cksum = chrp_checksum_add(cksum, lo);
+        chrp_checksum_add(cksum, hi)
     }
 
     pub fn serialize(&self, v: &mut Vec<u8>) {
@@ -149,26 +317,92 @@ pub struct Variable<'a> {
     pub value: Cow<'a, [u8]>,
 }
 
-impl Variable<'_> {
-    pub fn new<'a>(key: &'a [u8], value: &'a [u8]) -> Variable<'a> {
+impl<'a> Variable<'a> {
+    /// `value` as stored, already escaped.
+    pub fn new(key: &'a [u8], value: &'a [u8]) -> Variable<'a> {
         Variable {
             key,
             value: Cow::Borrowed(value),
         }
     }
+
+    /// Escapes raw `value` for storing.
+    pub fn from_bytes(key: &'a [u8], value: &[u8]) -> Variable<'a> {
+        Variable {
+            key,
+            value: Cow::Owned(EscapeVal::new(value.iter().copied()).collect()),
+        }
+    }
+
+    pub fn from_str(key: &'a [u8], value: &str) -> Variable<'a> {
+        Variable::from_bytes(key, value.as_bytes())
+    }
+
+    pub fn from_u32_le(key: &'a [u8], value: u32) -> Variable<'a> {
+        Variable::from_bytes(key, &value.to_le_bytes())
+    }
+
+    /// As `true` or `false`, the way the firmware writes flags.
+    pub fn from_bool(key: &'a [u8], value: bool) -> Variable<'a> {
+        Variable::from_str(key, if value { "true" } else { "false" })
+    }
+
+    /// In the upper-case hyphenated form macOS writes.
+    #[cfg(feature = "uuid")]
+    pub fn from_uuid(key: &'a [u8], value: uuid::Uuid) -> Variable<'a> {
+        let mut buf = uuid::Uuid::encode_buffer();
+        Variable::from_str(key, value.hyphenated().encode_upper(&mut buf))
+    }
+
+    /// The raw value, unescaped.
+    pub fn bytes(&self) -> Vec<u8> {
+        UnescapeVal::new(self.value.iter().copied()).collect()
+    }
+
+    pub fn as_str(&self) -> Option<String> {
+        String::from_utf8(self.bytes()).ok()
+    }
+
+    /// Exactly four bytes, little-endian.
+    pub fn as_u32_le(&self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes().try_into().ok()?))
+    }
+
+    /// `true` or `false`, or a single byte of 1 or 0.
+    pub fn as_bool(&self) -> Option<bool> {
+        match &self.bytes()[..] {
+            b"true" | [1] => Some(true),
+            b"false" | [0] => Some(false),
+            _ => None,
+        }
+    }
+
+    /// The text form with or without a trailing NUL, or 16 raw bytes.
+    #[cfg(feature = "uuid")]
+    pub fn as_uuid(&self) -> Option<uuid::Uuid> {
+        let bytes = self.bytes();
+        if let Ok(raw) = <[u8; 16]>::try_from(&bytes[..]) {
+            return Some(uuid::Uuid::from_bytes(raw));
+        }
+        let text = bytes.strip_suffix(&[0]).unwrap_or(&bytes);
+        uuid::Uuid::try_parse_ascii(text).ok()
+    }
 }
 
 #[derive(Clone)]
 pub struct Section<'a> {
     pub header: CHRPHeader<'a>,
-    pub values: HashMap<&'a [u8], Variable<'a>>,
+    /// In on-device order. Use `shift_remove` to keep it when deleting.
+    pub values: VarMap<'a>,
 }
 
 impl Section<'_> {
-    pub fn parse(mut nvr: &[u8]) -> Result<Section<'_>> {
-        let header = CHRPHeader::parse(&nvr[..16])?;
-        nvr = &nvr[16..];
-        let mut values = HashMap::new();
+    pub fn parse(nvr: &[u8]) -> Result<Section<'_>> {
+        let header = CHRPHeader::parse(nvr)?;
+        let mut nvr = nvr
+            .get(16..header.size as usize * 16)
+            .ok_or(Error::ParseError)?;
+        let mut values = VarMap::default();
         loop {
             let zero = slice_find(nvr, &0);
             if zero.is_none() {
@@ -185,10 +419,66 @@ impl Section<'_> {
             values.insert(key, Variable::new(key, &cand[(eq + 1)..]));
             nvr = &nvr[(zero + 1)..]
         }
+        trace!(?header, variables = values.len(), "parsed section");
         Ok(Section { header, values })
     }
-    fn size_bytes(&self) -> usize {
-        (self.header.size * 16) as usize
+    /// Like [`Section::parse`], but ignores the header checksum and skips
+    /// entries that do not look like `key=value` instead of stopping there.
+    /// `size` stands in for a size field that does not fit in `nvr`.
+    pub fn salvage<'a>(nvr: &'a [u8], name: &'a [u8], size: usize) -> Section<'a> {
+        let stored = nvr.get(2..4).map_or(0, |_| le_u16(nvr, 2) as usize * 16);
+        let size = if (32..=nvr.len()).contains(&stored) {
+            stored
+        } else {
+            size.min(nvr.len())
+        } & !15;
+        let mut values = VarMap::default();
+        for entry in nvr.get(16..size).unwrap_or_default().split(|&c| c == 0) {
+            // Two NULs in a row: the rest is free space.
+            if entry.is_empty() {
+                break;
+            }
+            let Some(eq) = slice_find(entry, &b'=') else {
+                trace!(len = entry.len(), "skipping entry without =");
+                continue;
+            };
+            let key = &entry[..eq];
+            if key.is_empty() || !key.iter().all(|c| c.is_ascii_graphic()) {
+                trace!(len = entry.len(), "skipping entry with a garbled name");
+                continue;
+            }
+            values.insert(key, Variable::new(key, &entry[(eq + 1)..]));
+        }
+        debug!(
+            name = %String::from_utf8_lossy(name),
+            variables = values.len(),
+            "salvaged section"
+        );
+        Section {
+            header: CHRPHeader {
+                name,
+                size: (size / 16) as u16,
+                signature: 0x70,
+            },
+            values,
+        }
+    }
+    /// Size of the section on flash, header included.
+    pub fn size_bytes(&self) -> usize {
+        self.header.size as usize * 16
+    }
+    /// Bytes taken up by the header and the `key=value\0` entries. A section
+    /// with no slot on flash, like `system` in a flat store without one, takes
+    /// up nothing until something is put in it.
+    pub fn used_bytes(&self) -> usize {
+        if self.size_bytes() == 0 && self.values.is_empty() {
+            return 0;
+        }
+        16 + self
+            .values
+            .values()
+            .map(|v| v.key.len() + v.value.len() + 2)
+            .sum::<usize>()
     }
     pub fn serialize(&self, v: &mut Vec<u8>) -> Result<()> {
         let start_size = v.len();
@@ -212,7 +502,7 @@ impl Section<'_> {
 
 struct SectionDebug<'a, 'b>(&'a Section<'b>);
 impl Debug for SectionDebug<'_, '_> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         let mut m = f.debug_map();
         for v in self.0.values.values() {
             m.entry(
@@ -228,7 +518,7 @@ impl Debug for SectionDebug<'_, '_> {
 }
 
 impl Debug for Section<'_> {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("Section")
             .field("header", &self.header)
             .field("values", &SectionDebug(self))
@@ -240,23 +530,35 @@ impl Debug for Section<'_> {
 pub struct Partition<'a> {
     pub header: CHRPHeader<'a>,
     pub generation: u32,
+    /// The adler32 checksum as read from flash. `serialize` computes a fresh
+    /// one instead of writing this back, see [`Partition::checksum`].
+    pub adler32: u32,
     pub common: Section<'a>,
     pub system: Section<'a>,
 }
 
 impl Partition<'_> {
     pub fn parse(nvr: &[u8]) -> Result<Partition<'_>> {
-        let header = CHRPHeader::parse(&nvr[..16])?;
-        if header.name != b"nvram" {
+        let header = CHRPHeader::parse(nvr)?;
+        if header.name != b"nvram" || nvr.len() < 32 {
             return Err(Error::ParseError);
         }
-        let adler = u32::from_le_bytes(nvr[16..20].try_into().unwrap());
-        let generation = u32::from_le_bytes(nvr[20..24].try_into().unwrap());
+        let adler = le_u32(nvr, 16);
+        let generation = le_u32(nvr, 20);
         let sec1 = Section::parse(&nvr[32..])?;
-        let sec2 = Section::parse(&nvr[(32 + sec1.size_bytes())..])?;
-        let calc_adler =
-            adler32::adler32(&nvr[20..(32 + sec1.size_bytes() + sec2.size_bytes())]).unwrap();
+        let sec2 = Section::parse(nvr.get((32 + sec1.size_bytes())..).unwrap_or_default())?;
+        let end = 32 + sec1.size_bytes() + sec2.size_bytes();
+        let calc_adler = Self::checksum(&nvr[..end]);
+        trace!(
+            generation,
+            first_section_size = sec1.size_bytes(),
+            second_section_size = sec2.size_bytes(),
+            adler = format_args!("{adler:#010x}"),
+            computed = format_args!("{calc_adler:#010x}"),
+            "parsed partition"
+        );
         if adler != calc_adler {
+            debug!(generation, "partition adler32 mismatch");
             return Err(Error::ParseError);
         }
         let mut com = None;
@@ -277,13 +579,74 @@ impl Partition<'_> {
         Ok(Partition {
             header,
             generation,
+            adler32: adler,
             common: com.unwrap(),
             system: sys.unwrap(),
         })
     }
-    fn size_bytes(&self) -> usize {
+    /// Recovers what it can from a damaged partition, ignoring checksums and
+    /// assuming the usual layout of `common` followed by `system`. The result
+    /// has fresh headers, so it serializes into a valid partition. Returns
+    /// `None` if no variable could be recovered.
+    pub fn salvage(nvr: &[u8]) -> Option<Partition<'_>> {
+        let nvr = nvr.get(..BANK_SIZE).unwrap_or(nvr);
+        if nvr.len() < 64 {
+            return None;
+        }
+        let generation = le_u32(nvr, 20);
+        let adler32 = le_u32(nvr, 16);
+        // Leave at least a header's worth of room for system.
+        let common = Section::salvage(&nvr[32..nvr.len() - 16], b"common", COMMON_SIZE);
+        let rest = &nvr[32 + common.size_bytes()..];
+        let mut system = Section::salvage(rest, b"system", rest.len());
+        if common.values.is_empty() && system.values.is_empty() {
+            return None;
+        }
+        // system takes up the rest of the bank, as in a real store.
+        system.header.size = ((BANK_SIZE - 32 - common.size_bytes()) / 16) as u16;
+        Some(Partition {
+            header: CHRPHeader {
+                name: b"nvram",
+                size: (BANK_SIZE / 16) as u16,
+                signature: 0x5a,
+            },
+            generation,
+            adler32,
+            common,
+            system,
+        })
+    }
+    /// A freshly formatted partition at generation 1, with no variables.
+    pub fn empty() -> Partition<'static> {
+        let section = |name, size: usize| Section {
+            header: CHRPHeader {
+                name,
+                size: (size / 16) as u16,
+                signature: 0x70,
+            },
+            values: VarMap::default(),
+        };
+        Partition {
+            header: CHRPHeader {
+                name: b"nvram",
+                size: (BANK_SIZE / 16) as u16,
+                signature: 0x5a,
+            },
+            generation: 1,
+            adler32: 0,
+            common: section(b"common", COMMON_SIZE),
+            system: section(b"system", BANK_SIZE - 32 - COMMON_SIZE),
+        }
+    }
+    /// Size of the partition on flash, both sections included.
+    pub fn size_bytes(&self) -> usize {
         32 + self.common.size_bytes() + self.system.size_bytes()
     }
+    /// Computes the adler32 checksum of a raw partition, which covers
+    /// everything after the checksum field itself.
+    pub fn checksum(nvr: &[u8]) -> u32 {
+        adler32::RollingAdler32::from_buffer(nvr.get(20..).unwrap_or_default()).hash()
+    }
     pub fn serialize(&self, v: &mut Vec<u8>) -> Result<()> {
         self.header.serialize(v);
         v.extend_from_slice(&[0; 4]);
@@ -293,29 +656,59 @@ impl Partition<'_> {
         self.common.serialize(v)?;
         self.system.serialize(v)?;
         let adler_end = v.len();
-        let adler = adler32::adler32(&v[adler_start..adler_end]).unwrap();
+        let adler = Self::checksum(&v[adler_start - 20..adler_end]);
         v[(adler_start - 4)..adler_start].copy_from_slice(&adler.to_le_bytes());
+        trace!(
+            generation = self.generation,
+            bytes = adler_end - adler_start + 20,
+            adler = format_args!("{adler:#010x}"),
+            "serialized partition"
+        );
         Ok(())
     }
 }
 
+// Where `system` starts in a freshly formatted partition.
+const COMMON_SIZE: usize = 0x7000;
+
+/// Size of one bank, each holding a complete partition.
+pub const BANK_SIZE: usize = 0x10000;
+/// Size of a full store: two banks.
+pub const NVRAM_SIZE: usize = 2 * BANK_SIZE;
+
 #[derive(Debug)]
 pub struct Nvram<'a> {
     pub partitions: [Partition<'a>; 2],
     pub active: usize,
+    pub layout: Layout<'a>,
 }
 
 impl<'a> Nvram<'a> {
+    /// Parses whatever banks are complete. A store shorter than [`NVRAM_SIZE`]
+    /// may still be readable, but should not be written back.
     pub fn parse(nvr: &[u8]) -> Result<Nvram<'_>> {
+        let format = Format::detect(nvr);
+        debug!(?format, "detected format");
+        match format {
+            Some(Format::V3) => return Err(Error::UnsupportedVersion(3)),
+            Some(Format::Flat) => return Nvram::parse_flat(nvr),
+            _ => {}
+        }
+        if nvr.len() < NVRAM_SIZE {
+            debug!(len = nvr.len(), "image is truncated");
+        }
         let p1;
         let p2;
-        match (Partition::parse(nvr), Partition::parse(&nvr[0x10000..])) {
+        let bank1 = nvr.get(BANK_SIZE..).unwrap_or_default();
+        match (Partition::parse(nvr), Partition::parse(bank1)) {
             (Err(err), Err(_)) => return Err(err),
             (Ok(p1r), Err(_)) => {
+                debug!("bank 1 at 0x10000 is invalid, using bank 0 for both");
                 p1 = p1r;
                 p2 = p1.clone();
             }
             (Err(_), Ok(p2r)) => {
+                debug!("bank 0 at 0x0 is invalid, using bank 1 for both");
                 p2 = p2r;
                 p1 = p2.clone();
             }
@@ -325,17 +718,106 @@ impl<'a> Nvram<'a> {
             }
         }
         let active = if p1.generation > p2.generation { 0 } else { 1 };
+        debug!(
+            active,
+            generations = ?[p1.generation, p2.generation],
+            "selected active bank"
+        );
         let partitions = [p1, p2];
-        Ok(Nvram { partitions, active })
+        Ok(Nvram {
+            partitions,
+            active,
+            layout: Layout::Banked,
+        })
+    }
+    // There is only one copy, both banks start out as it. A store without
+    // `system` gets an empty one that cannot take variables.
+    fn parse_flat(nvr: &[u8]) -> Result<Nvram<'_>> {
+        let (mut common, mut system) = (None, None);
+        let mut at = 0;
+        while let Ok(header) = CHRPHeader::parse(&nvr[at..]) {
+            let size = header.size as usize * 16;
+            trace!(at, name = %String::from_utf8_lossy(header.name), size, "flat partition");
+            match header.name {
+                b"common" => common = Some((at, Section::parse(&nvr[at..])?)),
+                b"system" => system = Some((at, Section::parse(&nvr[at..])?)),
+                _ => {}
+            }
+            if size == 0 || at + size > nvr.len() {
+                break;
+            }
+            at += size;
+        }
+        let (common_at, common) = common.ok_or(Error::ParseError)?;
+        let system_at = system.as_ref().map(|(at, _)| *at);
+        let system = system.map_or_else(
+            || Section {
+                header: CHRPHeader {
+                    name: b"system",
+                    size: 0,
+                    signature: 0x70,
+                },
+                values: VarMap::default(),
+            },
+            |(_, section)| section,
+        );
+        let part = Partition {
+            header: CHRPHeader {
+                name: b"nvram",
+                size: (nvr.len() / 16) as u16,
+                signature: 0x5a,
+            },
+            generation: 0,
+            adler32: 0,
+            common,
+            system,
+        };
+        Ok(Nvram {
+            partitions: [part.clone(), part],
+            active: 0,
+            layout: Layout::Flat {
+                image: nvr,
+                common_at,
+                system_at,
+            },
+        })
     }
     pub fn serialize(&self) -> Result<Vec<u8>> {
-        let mut v = Vec::with_capacity(self.partitions[0].size_bytes() * 2);
-        self.partitions[0].serialize(&mut v)?;
-        self.partitions[1].serialize(&mut v)?;
+        let Layout::Flat {
+            image,
+            common_at,
+            system_at,
+        } = self.layout
+        else {
+            let mut v = Vec::with_capacity(self.partitions[0].size_bytes() * 2);
+            self.partitions[0].serialize(&mut v)?;
+            self.partitions[1].serialize(&mut v)?;
+            return Ok(v);
+        };
+        let part = &self.partitions[self.active];
+        let mut v = image.to_vec();
+        let mut put = |at: usize, section: &Section| {
+            let mut bytes = Vec::with_capacity(section.size_bytes());
+            section.serialize(&mut bytes)?;
+            v[at..at + bytes.len()].copy_from_slice(&bytes);
+            Ok(())
+        };
+        put(common_at, &part.common)?;
+        match system_at {
+            Some(at) => put(at, &part.system)?,
+            None if !part.system.values.is_empty() => return Err(Error::SectionTooBig),
+            None => {}
+        }
         Ok(v)
     }
     pub fn prepare_for_write(&mut self) {
         let inactive = 1 - self.active;
+        debug!(
+            from = self.active,
+            to = inactive,
+            generation = self.partitions[self.active].generation + 1,
+            "staging new generation"
+        );
         self.partitions[inactive] = self.partitions[self.active].clone();
         self.partitions[inactive].generation += 1;
         self.active = inactive;
@@ -345,36 +827,225 @@ impl<'a> Nvram<'a> {
     }
 }
 
-#[repr(C)]
-pub struct EraseInfoUser {
-    start: u32,
-    length: u32,
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-#[repr(C)]
-#[derive(Default)]
-pub struct MtdInfoUser {
-    ty: u8,
-    flags: u32,
-    size: u32,
-    erasesize: u32,
-    writesize: u32,
-    oobsize: u32,
-    padding: u64,
-}
+    // The start of a bank as it sits on flash: signature, header checksum,
+    // size in 16-byte units and name, then adler32 and generation.
+    const HEADER: [u8; 24] = [
+        0x5a, 0x90, 0x00, 0x10, b'n', b'v', b'r', b'a', b'm', 0, 0, 0, 0, 0, 0, 0, 0x78, 0x56,
+        0x34, 0x12, 0x2a, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn header_fields_are_little_endian() {
+        assert_eq!(le_u16(&HEADER, 2), 0x1000);
+        assert_eq!(le_u32(&HEADER, 16), 0x12345678);
+        assert_eq!(le_u32(&HEADER, 20), 42);
+        let header = CHRPHeader::parse(&HEADER).unwrap();
+        assert_eq!(header.name, b"nvram");
+        assert_eq!(header.size as usize * 16, BANK_SIZE);
+    }
+
+    #[test]
+    fn partition_keeps_generation_and_adler32() {
+        let mut part = Partition::empty();
+        part.generation = 42;
+        let mut data = Vec::new();
+        part.serialize(&mut data).unwrap();
+        let parsed = Partition::parse(&data).unwrap();
+        assert_eq!(parsed.generation, 42);
+        assert_eq!(parsed.adler32, le_u32(&data, 16));
+        assert_eq!(parsed.adler32, Partition::checksum(&data));
+    }
+
+    #[test]
+    fn bad_adler32_is_an_error() {
+        let mut data = Vec::new();
+        Partition::empty().serialize(&mut data).unwrap();
+        data[20] ^= 1;
+        assert!(matches!(Partition::parse(&data), Err(Error::ParseError)));
+    }
+
+    #[test]
+    fn short_buffers_are_errors() {
+        assert!(matches!(
+            CHRPHeader::parse(&HEADER[..15]),
+            Err(Error::ParseError)
+        ));
+        assert!(matches!(Partition::parse(&HEADER), Err(Error::ParseError)));
+        assert!(matches!(Partition::parse(&[]), Err(Error::ParseError)));
+    }
+
+    fn image(vars: &[(&'static [u8], &str)]) -> Vec<u8> {
+        let mut part = Partition::empty();
+        for (key, value) in vars {
+            part.system
+                .values
+                .insert(key, Variable::from_str(key, value));
+        }
+        let mut data = Vec::new();
+        part.serialize(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn salvage_ignores_the_checksum() {
+        let mut data = image(&[(b"boot-volume", "a"), (b"auto-boot", "true")]);
+        data[16] ^= 1;
+        assert!(Partition::parse(&data).is_err());
+        let part = Partition::salvage(&data).unwrap();
+        let names = part.system.values.keys().copied().collect::<Vec<_>>();
+        assert_eq!(names, [&b"boot-volume"[..], b"auto-boot"]);
+        assert_eq!(
+            part.system.values[&b"auto-boot"[..]].as_str().unwrap(),
+            "true"
+        );
+        // The result is a valid partition again.
+        let mut fixed = Vec::new();
+        part.serialize(&mut fixed).unwrap();
+        assert!(Partition::parse(&fixed).is_ok());
+    }
+
+    #[test]
+    fn salvage_skips_garbled_entries() {
+        let mut data = image(&[(b"first", "1"), (b"second", "2"), (b"third", "3")]);
+        let find = |data: &[u8], what: &[u8]| data.windows(what.len()).position(|w| w == what);
+        // A name with a control character, and an entry without '='.
+        let first = find(&data, b"first=").unwrap();
+        data[first] = 0x01;
+        let third = find(&data, b"third=").unwrap();
+        data[third + 5] = b'x';
+        let part = Partition::salvage(&data).unwrap();
+        let names = part.system.values.keys().copied().collect::<Vec<_>>();
+        assert_eq!(names, [&b"second"[..]]);
+    }
+
+    #[test]
+    fn salvage_gives_up_on_nothing() {
+        assert!(Partition::salvage(&image(&[])).is_none());
+        assert!(Partition::salvage(&[0; 63]).is_none());
+        assert!(Partition::salvage(&[0xff; BANK_SIZE]).is_none());
+    }
+
+    #[test]
+    fn detects_formats() {
+        assert_eq!(Format::detect(&image(&[])), Some(Format::Chrp));
+        assert_eq!(Format::detect(&HEADER), Some(Format::Chrp));
+        let mut flat = Vec::new();
+        CHRPHeader {
+            name: b"common",
+            size: 2,
+            signature: 0x70,
+        }
+        .serialize(&mut flat);
+        assert_eq!(Format::detect(&flat), Some(Format::Flat));
+        assert_eq!(Format::detect(b"3VVN\0\0\0\0"), Some(Format::V3));
+        assert_eq!(Format::detect(b"NVV3"), Some(Format::V3));
+    }
 
-nix::ioctl_write_ptr!(mtd_mem_erase, b'M', 2, EraseInfoUser);
-nix::ioctl_read!(mtd_mem_get_info, b'M', 1, MtdInfoUser);
+    #[test]
+    fn flat_store_without_system_takes_writes_to_common() {
+        let mut flat = Vec::new();
+        CHRPHeader {
+            name: b"common",
+            size: 4,
+            signature: 0x70,
+        }
+        .serialize(&mut flat);
+        flat.resize(64, 0);
+        let mut nv = Nvram::parse(&flat).unwrap();
+        let system = &nv.partitions[nv.active].system;
+        assert_eq!((system.used_bytes(), system.size_bytes()), (0, 0));
+        nv.prepare_for_write();
+        nv.active_part_mut()
+            .common
+            .values
+            .insert(b"foo", Variable::from_str(b"foo", "bar"));
+        let data = nv.serialize().unwrap();
+        assert_eq!(data.len(), flat.len());
+        let nv = Nvram::parse(&data).unwrap();
+        let part = &nv.partitions[nv.active];
+        assert_eq!(part.common.values[&b"foo"[..]].as_str().unwrap(), "bar");
+        assert!(part.system.used_bytes() <= part.system.size_bytes());
+        // Anything put in the missing section still has nowhere to go.
+        let mut nv = nv;
+        nv.active_part_mut()
+            .system
+            .values
+            .insert(b"foo", Variable::from_str(b"foo", "bar"));
+        assert!(nv.partitions[nv.active].system.used_bytes() > 0);
+        assert!(matches!(nv.serialize(), Err(Error::SectionTooBig)));
+    }
+
+    #[test]
+    fn detects_nothing_in_garbage() {
+        assert_eq!(Format::detect(&[]), None);
+        assert_eq!(Format::detect(b"NVV"), None);
+        assert_eq!(Format::detect(b"not an nvram image at all"), None);
+        let mut bad = HEADER;
+        bad[1] ^= 1;
+        assert_eq!(Format::detect(&bad), None);
+    }
 
-pub fn erase_if_needed(file: &File, size: usize) {
-    if unsafe { mtd_mem_get_info(file.as_raw_fd(), &mut MtdInfoUser::default()) }.is_err() {
-        return;
+    #[test]
+    fn escaping_round_trips() {
+        let mut raw = b"a\0\0\xffb".to_vec();
+        raw.extend([0; 200]);
+        raw.extend([0xff; 3]);
+        let var = Variable::from_bytes(b"k", &raw);
+        assert!(!var.value.contains(&0));
+        assert_eq!(&var.value[..6], b"a\xff\x02\xff\x81b");
+        assert_eq!(var.bytes(), raw);
+        assert_eq!(Variable::new(b"k", &var.value).bytes(), raw);
     }
-    let erase_info = EraseInfoUser {
-        start: 0,
-        length: size as u32,
-    };
-    unsafe {
-        mtd_mem_erase(file.as_raw_fd(), &erase_info).unwrap();
+
+    #[test]
+    fn u32_needs_four_bytes() {
+        let var = Variable::from_u32_le(b"k", 0x12345678);
+        assert_eq!(var.bytes(), [0x78, 0x56, 0x34, 0x12]);
+        assert_eq!(var.as_u32_le(), Some(0x12345678));
+        assert_eq!(Variable::from_u32_le(b"k", 0).as_u32_le(), Some(0));
+        for raw in [&[1, 2, 3][..], &[1, 2, 3, 4, 5], &[]] {
+            assert_eq!(Variable::from_bytes(b"k", raw).as_u32_le(), None);
+        }
+    }
+
+    #[test]
+    fn strings_must_be_utf8() {
+        assert_eq!(Variable::from_str(b"k", "héllo").as_str().unwrap(), "héllo");
+        assert_eq!(
+            Variable::from_bytes(b"k", &[b'a', 0xff, 0xfe]).as_str(),
+            None
+        );
+    }
+
+    #[test]
+    fn bools() {
+        assert_eq!(Variable::from_bool(b"k", true).bytes(), b"true");
+        assert_eq!(Variable::from_bool(b"k", false).as_bool(), Some(false));
+        assert_eq!(Variable::from_bytes(b"k", &[1]).as_bool(), Some(true));
+        assert_eq!(Variable::from_bytes(b"k", &[0]).as_bool(), Some(false));
+        assert_eq!(Variable::from_str(b"k", "yes").as_bool(), None);
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn uuids() {
+        let id = uuid::Uuid::from_u128(0x0123abcd_4567_89ef_0123_456789abcdef);
+        let var = Variable::from_uuid(b"k", id);
+        assert_eq!(
+            var.as_str().unwrap(),
+            "0123ABCD-4567-89EF-0123-456789ABCDEF"
+        );
+        assert_eq!(var.as_uuid(), Some(id));
+        let nul = Variable::from_str(b"k", "0123abcd-4567-89ef-0123-456789abcdef\0");
+        assert_eq!(nul.as_uuid(), Some(id));
+        assert_eq!(
+            Variable::from_bytes(b"k", id.as_bytes()).as_uuid(),
+            Some(id)
+        );
+        assert_eq!(Variable::from_str(b"k", "not a uuid").as_uuid(), None);
     }
 }
@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: MIT
+//! MTD devices: erasing, erase block sizes and finding the nvram partition.
+
+use std::{fs::File, os::unix::io::AsRawFd, path::PathBuf};
+
+use tracing::debug;
+
+// The MTD ioctl structs are in host byte order, unlike the flash contents.
+#[repr(C)]
+pub struct EraseInfoUser {
+    start: u32,
+    length: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+pub struct MtdInfoUser {
+    ty: u8,
+    flags: u32,
+    size: u32,
+    erasesize: u32,
+    writesize: u32,
+    oobsize: u32,
+    padding: u64,
+}
+
+nix::ioctl_write_ptr!(mtd_mem_erase, b'M', 2, EraseInfoUser);
+nix::ioctl_read!(mtd_mem_get_info, b'M', 1, MtdInfoUser);
+
+fn mtd_info(file: &File) -> Option<MtdInfoUser> {
+    let mut info = MtdInfoUser::default();
+    unsafe { mtd_mem_get_info(file.as_raw_fd(), &mut info) }.ok()?;
+    Some(info)
+}
+
+/// Erase block size of `file`, or `None` if it is not an MTD device.
+pub fn erase_size(file: &File) -> Option<usize> {
+    mtd_info(file).map(|info| info.erasesize as usize)
+}
+
+/// Erases the blocks covering `start..start + len`, rounded out to the
+/// device's erase block size. Files that are not MTD devices are left alone.
+pub fn erase_range(file: &File, start: usize, len: usize) -> nix::Result<()> {
+    let Some(info) = mtd_info(file) else {
+        debug!("not an MTD device, skipping erase");
+        return Ok(());
+    };
+    let block = (info.erasesize as usize).max(1);
+    let first = start / block * block;
+    let end = (start + len).div_ceil(block) * block;
+    debug!(
+        start = first,
+        length = end - first,
+        erasesize = block,
+        "erasing"
+    );
+    let erase_info = EraseInfoUser {
+        start: first as u32,
+        length: (end - first) as u32,
+    };
+    unsafe { mtd_mem_erase(file.as_raw_fd(), &erase_info) }.map(|_| ())
+}
+
+/// The MTD device of the partition named "nvram" in /proc/mtd, if any.
+pub fn find_mtd() -> Option<PathBuf> {
+    let mtd = std::fs::read_to_string("/proc/mtd").ok()?;
+    mtd.lines().find_map(|line| {
+        let (dev, rest) = line.split_once(':')?;
+        rest.trim_end()
+            .ends_with("\"nvram\"")
+            .then(|| PathBuf::from(format!("/dev/{dev}")))
+    })
+}
+
+/// Erases the first `size` bytes of `file` if it is an MTD device, see
+/// [`erase_range`].
+pub fn erase_if_needed(file: &File, size: usize) -> nix::Result<()> {
+    erase_range(file, 0, size)
+}
@@ -0,0 +1,354 @@
+// SPDX-License-Identifier: MIT
+//! Backends holding a store. An [`NvramStore`] reads and writes whole
+//! images and does whatever erasing its medium needs, so callers only deal
+//! in bytes and [`Nvram`]. [`MemoryNvram`] stands in for one in tests.
+//! [`MappedStore`] maps a file so it can be parsed in place.
+
+use std::{
+    ffi::c_void,
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::unix::io::AsRawFd,
+    path::Path,
+    ptr, slice,
+};
+
+use nix::{
+    sys::mman::{mmap, msync, munmap, MapFlags, MsFlags, ProtFlags},
+    unistd::{sysconf, SysconfVar},
+};
+use tracing::debug;
+
+use crate::{
+    erase_range, erase_size, Error, EscapeVal, Layout, Nvram, Partition, Result, Variable,
+};
+
+pub trait NvramStore {
+    /// The whole store, both banks.
+    fn read(&mut self) -> Result<Vec<u8>>;
+
+    /// Replaces the store with `data`.
+    fn write(&mut self, data: &[u8]) -> Result<()>;
+
+    /// Serializes `nv` and writes it, returning the size of the image.
+    fn write_nvram(&mut self, nv: &Nvram) -> Result<usize> {
+        let data = nv.serialize()?;
+        self.write(&data)?;
+        Ok(data.len())
+    }
+}
+
+fn read_file(file: &mut File) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    file.rewind().map_err(Error::IoError)?;
+    file.read_to_end(&mut data).map_err(Error::IoError)?;
+    Ok(data)
+}
+
+/// An MTD device. Only the erase blocks that change are erased and
+/// rewritten, which for a normal write is just the bank taking the new
+/// generation.
+pub struct MtdStore(pub File);
+
+impl NvramStore for MtdStore {
+    fn read(&mut self) -> Result<Vec<u8>> {
+        read_file(&mut self.0)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let old = read_file(&mut self.0)?;
+        let block = erase_size(&self.0).unwrap_or(data.len()).max(1);
+        for (i, chunk) in data.chunks(block).enumerate() {
+            let start = i * block;
+            if old.get(start..start + chunk.len()) == Some(chunk) {
+                continue;
+            }
+            debug!(start, "rewriting erase block");
+            erase_range(&self.0, start, chunk.len()).map_err(|e| Error::IoError(e.into()))?;
+            self.0
+                .seek(SeekFrom::Start(start as u64))
+                .map_err(Error::IoError)?;
+            self.0.write_all(chunk).map_err(Error::IoError)?;
+        }
+        Ok(())
+    }
+}
+
+/// Anything that takes plain writes: an nvmem provider or an image file.
+pub struct FileStore(pub File);
+
+impl NvramStore for FileStore {
+    fn read(&mut self) -> Result<Vec<u8>> {
+        read_file(&mut self.0)
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.0.rewind().map_err(Error::IoError)?;
+        self.0.write_all(data).map_err(Error::IoError)
+    }
+}
+
+/// A store kept in memory, for testing code built on this crate without a
+/// device or a dump. Every write replaces `data`.
+#[derive(Clone, Debug)]
+pub struct MemoryNvram {
+    pub data: Vec<u8>,
+    /// How many times the store has been written.
+    pub writes: usize,
+}
+
+impl MemoryNvram {
+    /// Seeded with an image, e.g. a dump of a real store.
+    pub fn new(data: Vec<u8>) -> MemoryNvram {
+        MemoryNvram { data, writes: 0 }
+    }
+
+    /// A freshly formatted store holding `common` and `system`, given as
+    /// names and raw values, in both banks.
+    pub fn with_vars(common: &[(&str, &[u8])], system: &[(&str, &[u8])]) -> Result<MemoryNvram> {
+        let escape = |vars: &[(&str, &[u8])]| {
+            vars.iter()
+                .map(|(k, v)| {
+                    (
+                        k.as_bytes().to_vec(),
+                        EscapeVal::new(v.iter().copied()).collect(),
+                    )
+                })
+                .collect::<Vec<(Vec<u8>, Vec<u8>)>>()
+        };
+        let (common, system) = (escape(common), escape(system));
+        let mut part = Partition::empty();
+        for (section, vars) in [(&mut part.common, &common), (&mut part.system, &system)] {
+            for (k, v) in vars {
+                section.values.insert(k, Variable::new(k, v));
+            }
+        }
+        let nv = Nvram {
+            partitions: [part.clone(), part],
+            active: 1,
+            layout: Layout::Banked,
+        };
+        Ok(MemoryNvram::new(nv.serialize()?))
+    }
+}
+
+impl NvramStore for MemoryNvram {
+    fn read(&mut self) -> Result<Vec<u8>> {
+        Ok(self.data.clone())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.data = data.to_vec();
+        self.writes += 1;
+        Ok(())
+    }
+}
+
+/// A file mapped into memory, for parsing an image without copying it:
+/// `Nvram::parse(store.bytes())`. Writes only touch the pages that change,
+/// normally just the bank taking the new generation. MTD devices cannot be
+/// mapped; use [`MtdStore`] for those.
+///
+/// The mapping is shared, so anything else writing the file shows through.
+/// That is why it can only be made with `unsafe`, like memmap2's maps.
+pub struct MappedStore {
+    file: File,
+    map: *mut u8,
+    len: usize,
+    writable: bool,
+}
+
+// msync() takes whole pages, which are 16K on Apple silicon.
+fn page_size() -> usize {
+    match sysconf(SysconfVar::PAGE_SIZE) {
+        Ok(Some(size)) if size > 0 => size as usize,
+        _ => 4096,
+    }
+}
+
+impl MappedStore {
+    /// Maps `file`, writable too if `write` is set.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated by anyone else, this or
+    /// another process, while the store or a slice from [`Self::bytes`] is
+    /// alive. Otherwise those slices change under their borrows, which is
+    /// undefined behaviour.
+    pub unsafe fn new(file: File, write: bool) -> Result<MappedStore> {
+        let len = file.metadata().map_err(Error::IoError)?.len() as usize;
+        if len == 0 {
+            return Err(Error::ParseError);
+        }
+        let prot = match write {
+            true => ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+            false => ProtFlags::PROT_READ,
+        };
+        let map = unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                prot,
+                MapFlags::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        }
+        .map_err(|e| Error::IoError(e.into()))?;
+        Ok(MappedStore {
+            file,
+            map: map as *mut u8,
+            len,
+            writable: write,
+        })
+    }
+
+    /// Maps `path`, writable too if `write` is set.
+    ///
+    /// # Safety
+    ///
+    /// As for [`Self::new`].
+    pub unsafe fn open(path: impl AsRef<Path>, write: bool) -> Result<MappedStore> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(write)
+            .open(path)
+            .map_err(Error::IoError)?;
+        unsafe { MappedStore::new(file, write) }
+    }
+
+    /// The image, straight from the mapping.
+    pub fn bytes(&self) -> &[u8] {
+        // The constructors' callers promise nobody else changes the file.
+        unsafe { slice::from_raw_parts(self.map, self.len) }
+    }
+
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+}
+
+impl NvramStore for MappedStore {
+    fn read(&mut self) -> Result<Vec<u8>> {
+        Ok(self.bytes().to_vec())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        if !self.writable {
+            return Err(Error::IoError(io::ErrorKind::PermissionDenied.into()));
+        }
+        if data.len() != self.len {
+            return Err(Error::IoError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "image is not the size of the store",
+            )));
+        }
+        // The map starts on a page boundary, so every chunk does too.
+        let page_size = page_size();
+        for (i, chunk) in data.chunks(page_size).enumerate() {
+            let start = i * page_size;
+            if &self.bytes()[start..start + chunk.len()] == chunk {
+                continue;
+            }
+            debug!(start, "rewriting page");
+            unsafe {
+                let page = self.map.add(start);
+                ptr::copy_nonoverlapping(chunk.as_ptr(), page, chunk.len());
+                msync(page as *mut c_void, chunk.len(), MsFlags::MS_SYNC)
+                    .map_err(|e| Error::IoError(e.into()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for MappedStore {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = munmap(self.map as *mut c_void, self.len);
+        }
+    }
+}
+
+/// `file` as an [`MtdStore`] if it is an MTD device, a [`FileStore`]
+/// otherwise. Lock the file before handing it over if that is wanted.
+pub fn from_file(file: File) -> Box<dyn NvramStore> {
+    match erase_size(&file) {
+        Some(_) => Box::new(MtdStore(file)),
+        None => Box::new(FileStore(file)),
+    }
+}
+
+/// Opens `path` for reading, and for writing too if `write` is set.
+pub fn open(path: impl AsRef<Path>, write: bool) -> Result<Box<dyn NvramStore>> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(write)
+        .open(path)
+        .map_err(Error::IoError)?;
+    Ok(from_file(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BANK_SIZE;
+
+    fn store() -> MemoryNvram {
+        MemoryNvram::with_vars(&[("boot-args", b"debug=0x14e")], &[("auto-boot", b"true")]).unwrap()
+    }
+
+    fn set(store: &mut MemoryNvram, key: &'static [u8], value: &[u8]) {
+        let data = store.read().unwrap();
+        let mut nv = Nvram::parse(&data).unwrap();
+        nv.prepare_for_write();
+        nv.active_part_mut()
+            .system
+            .values
+            .insert(key, Variable::from_bytes(key, value));
+        store.write_nvram(&nv).unwrap();
+    }
+
+    #[test]
+    fn writes_read_back() {
+        let mut store = store();
+        set(&mut store, b"test", &[0, 1, 0xff]);
+        assert_eq!(store.writes, 1);
+        let data = store.read().unwrap();
+        let nv = Nvram::parse(&data).unwrap();
+        let part = &nv.partitions[nv.active];
+        assert_eq!(part.generation, 2);
+        assert_eq!(part.system.values[&b"test"[..]].bytes(), [0, 1, 0xff]);
+        assert_eq!(
+            part.common.values[&b"boot-args"[..]].as_str().unwrap(),
+            "debug=0x14e"
+        );
+    }
+
+    #[test]
+    fn seeded_store_reads_back() {
+        let mut store = MemoryNvram::new(store().data);
+        assert_eq!(store.read().unwrap(), store.data);
+        assert_eq!(store.writes, 0);
+    }
+
+    // Each bank is an erase block of its own: a write only rewrites the bank
+    // taking the new generation, leaving the previous state in the other.
+    #[test]
+    fn writes_alternate_banks() {
+        let mut store = store();
+        let mut before = store.data.clone();
+        for (i, expected) in [0, 1, 0].into_iter().enumerate() {
+            set(&mut store, b"count", &[i as u8]);
+            let changed = (0..2)
+                .filter(|b| {
+                    let bank = b * BANK_SIZE..(b + 1) * BANK_SIZE;
+                    before[bank.clone()] != store.data[bank]
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(changed, [expected]);
+            before = store.data.clone();
+        }
+        assert_eq!(store.writes, 3);
+    }
+}
@@ -1,13 +1,13 @@
 // SPDX-License-Identifier: MIT
 #![allow(dead_code)]
+use apple_nvram::{find_mtd, store, Nvram, NvramStore, Variable};
 use gpt::{disk::LogicalBlockSize, GptConfig};
-use apple_nvram::{erase_if_needed, Nvram, Variable};
 use std::{
-    borrow::Cow,
     collections::HashMap,
+    env,
     fs::{File, OpenOptions},
     io::{stdin, stdout, Read, Seek, SeekFrom, Write},
-    env
+    path::PathBuf,
 };
 use uuid::Uuid;
 
@@ -238,10 +238,45 @@ fn swap_uuid(u: &Uuid) -> Uuid {
     Uuid::from_fields(a.swap_bytes(), b.swap_bytes(), c.swap_bytes(), d)
 }
 
+// The volume group boot-volume in `store` points at, if it can be read.
+fn default_vg(store: &mut dyn NvramStore) -> Option<String> {
+    let data = store.read().ok()?;
+    let nv = Nvram::parse(&data).ok()?;
+    let var = nv.partitions[nv.active]
+        .system
+        .values
+        .get(b"boot-volume".as_ref())?;
+    let value = var.as_str()?;
+    Some(value.split(':').nth(2)?.to_ascii_uppercase())
+}
+
+// One object per candidate, for installers and other frontends.
+fn print_json(cands: &[BootCandidate], default: Option<&str>) {
+    let entries = cands
+        .iter()
+        .map(|cand| {
+            let vg = cand
+                .vg_uuid
+                .hyphenated()
+                .encode_upper(&mut Uuid::encode_buffer())
+                .to_owned();
+            serde_json::json!({
+                "vg_uuid": vg,
+                "name": cand.vol_names.join(", "),
+                "default": default == Some(vg.as_str()),
+            })
+        })
+        .collect::<Vec<_>>();
+    println!("{}", serde_json::Value::Array(entries));
+}
+
 fn main() {
     let mut nvram_key: &[u8] = b"boot-volume".as_ref();
+    let json = env::args().any(|arg| arg == "--json");
     for arg in env::args() {
-        if arg == "--next" || arg == "-n" {
+        // Boot the choice once, leaving boot-volume as it is. --nextonly is
+        // what macOS's bless calls it.
+        if arg == "--next" || arg == "-n" || arg == "--nextonly" {
             nvram_key = b"alt-boot-volume".as_ref();
         }
     }
@@ -264,6 +299,14 @@ fn main() {
             });
         }
     }
+    let device = find_mtd().unwrap_or_else(|| PathBuf::from("/dev/mtd0"));
+    if json {
+        let default = File::open(&device)
+            .ok()
+            .and_then(|file| default_vg(&mut *store::from_file(file)));
+        print_json(&cands, default.as_deref());
+        return;
+    }
     for (i, cand) in cands.iter().enumerate() {
         println!("{}) {}", i + 1, cand.vol_names.join(", "));
     }
@@ -287,24 +330,23 @@ fn main() {
             .hyphenated()
             .encode_upper(&mut Uuid::encode_buffer())
     );
-    let mut file = OpenOptions::new()
+    let file = OpenOptions::new()
         .read(true)
         .write(true)
-        .open("/dev/mtd0")
+        .open(&device)
         .unwrap();
-    let mut data = Vec::new();
-    file.read_to_end(&mut data).unwrap();
+    // Keeps asahi-nvram from writing in between our read and write.
+    if file.try_lock().is_err() {
+        eprintln!("{} is in use by another process", device.display());
+        std::process::exit(1);
+    }
+    let mut file = store::from_file(file);
+    let data = file.read().unwrap();
     let mut nv = Nvram::parse(&data).unwrap();
     nv.prepare_for_write();
-    nv.active_part_mut().system.values.insert(
-        nvram_key,
-        Variable {
-            key: nvram_key,
-            value: Cow::Owned(boot_str.into_bytes())
-        }
-    );
-    file.rewind().unwrap();
-    let data = nv.serialize().unwrap();
-    erase_if_needed(&file, data.len());
-    file.write_all(&data).unwrap();
+    nv.active_part_mut()
+        .system
+        .values
+        .insert(nvram_key, Variable::from_str(nvram_key, &boot_str));
+    file.write_nvram(&nv).unwrap();
 }
@@ -4,12 +4,12 @@ use std::{
     env,
     fmt::Debug,
     fs,
-    fs::OpenOptions,
-    io::{self, stdout, Read, Write},
+    io::{self, stdout, Write},
+    os::unix::fs::{DirBuilderExt, PermissionsExt},
     path::Path,
 };
 
-use apple_nvram::{Nvram, UnescapeVal, Variable};
+use apple_nvram::{store, Nvram, Variable};
 
 use ini::Ini;
 
@@ -20,7 +20,7 @@ enum Error {
     VariableNotFound,
     FileIO,
     BluezConfigDirNotFound,
-    SliceError,
+    Slice,
 }
 
 impl From<apple_nvram::Error> for Error {
@@ -28,6 +28,8 @@ impl From<apple_nvram::Error> for Error {
         match e {
             apple_nvram::Error::ParseError => Error::Parse,
             apple_nvram::Error::SectionTooBig => Error::SectionTooBig,
+            apple_nvram::Error::UnsupportedVersion(_) => Error::Parse,
+            apple_nvram::Error::IoError(_) => Error::FileIO,
         }
     }
 }
@@ -40,7 +42,7 @@ impl From<io::Error> for Error {
 
 impl From<std::array::TryFromSliceError> for Error {
     fn from(_e: std::array::TryFromSliceError) -> Self {
-        Error::SliceError
+        Error::Slice
     }
 }
 
@@ -56,7 +58,7 @@ fn real_main() -> Result<()> {
         .subcommand(clap::Command::new("list").about("Parse shared Bluetooth keys from nvram"))
         .subcommand(
             clap::Command::new("sync")
-                .about("Sync Bluetooth device information from nvram")
+                .about("Write Bluetooth pairing keys from nvram into the bluez config")
                 .arg(clap::arg!(-c --config [CONFIG] "Bluez config path."))
                 .arg(clap::Arg::new("variable").multiple_values(true)),
         )
@@ -69,12 +71,8 @@ fn real_main() -> Result<()> {
     let default_config = "/var/lib/bluetooth".to_owned();
     let bt_var = "BluetoothUHEDevices";
 
-    let mut file = OpenOptions::new()
-        .read(true)
-        .open(matches.get_one::<String>("device").unwrap_or(&default_name))
-        .unwrap();
-    let mut data = Vec::new();
-    file.read_to_end(&mut data).unwrap();
+    let device = matches.get_one::<String>("device").unwrap_or(&default_name);
+    let data = store::open(device, false)?.read()?;
     let mut nv = Nvram::parse(&data)?;
     let bt_devs = nv
         .active_part_mut()
@@ -105,10 +103,7 @@ fn real_main() -> Result<()> {
 }
 
 fn dump(var: &Variable) -> Result<()> {
-    let mut data = Vec::new();
-    for c in UnescapeVal::new(var.value.iter().copied()) {
-        data.push(c)
-    }
+    let data = var.bytes();
 
     stdout().write_all(&data)?;
     Ok(())
@@ -174,10 +169,7 @@ fn parse_bt_device(input: &mut &[u8]) -> Result<BtDevice> {
 }
 
 fn parse_bt_info(var: &Variable) -> Result<BtInfo> {
-    let mut data = Vec::new();
-    for c in UnescapeVal::new(var.value.iter().copied()) {
-        data.push(c)
-    }
+    let data = var.bytes();
 
     assert!(data.len() >= 8);
     let adapter_mac: [u8; 6] = data[0..6].try_into()?;
@@ -235,37 +227,50 @@ fn sync_btkeys(var: &Variable, config: &String) -> Result<()> {
 
     let adapter_path = config_path.join(format_mac(&info.mac)?);
 
+    // bluetoothd keeps its storage private to root.
+    let mut dirs = fs::DirBuilder::new();
+    dirs.mode(0o700);
     if !adapter_path.is_dir() {
-        fs::create_dir(adapter_path.clone())?;
+        dirs.create(&adapter_path)?;
     }
 
     for dev in info.devices {
         let dev_path = adapter_path.join(format_mac(&dev.mac)?);
 
         if !dev_path.is_dir() {
-            fs::create_dir(dev_path.clone())?;
+            dirs.create(&dev_path)?;
         }
 
         let info_file = dev_path.as_path().join("info");
-
-        if info_file.exists() {
-            continue;
-        }
-
-        let mut info = Ini::new();
-
-        info.with_section(Some("General"))
-            .set("Name", dev.name)
-            .set("Class", format!("{:#08X}", dev.class))
-            .set("Trusted", "true")
-            .set("Blocked", "false")
-            .set("WakeAllowed", "true");
+        let key = format_key(&dev.pairing_key)?;
+
+        // Devices paired before only get the key from macOS, whatever else
+        // bluez knows about them is kept.
+        let mut info = if info_file.exists() {
+            let info = Ini::load_from_file(&info_file).map_err(|_| Error::Parse)?;
+            if info.get_from(Some("LinkKey"), "Key") == Some(key.as_str()) {
+                continue;
+            }
+            info
+        } else {
+            let mut info = Ini::new();
+            info.with_section(Some("General"))
+                .set("Name", dev.name)
+                .set("Class", format!("{:#08X}", dev.class))
+                .set("Trusted", "true")
+                .set("Blocked", "false")
+                .set("WakeAllowed", "true");
+            info.with_section(Some("DeviceID"))
+                .set("Vendor", format!("{}", dev.vendor_id))
+                .set("Product", format!("{}", dev.product_id));
+            info
+        };
         info.with_section(Some("LinkKey"))
-            .set("Key", format_key(&dev.pairing_key)?);
-        info.with_section(Some("DeviceID"))
-            .set("Vendor", format!("{}", dev.vendor_id))
-            .set("Product", format!("{}", dev.product_id));
-        info.write_to_file(info_file)?;
+            .set("Key", key)
+            .set("Type", "4")
+            .set("PINLength", "0");
+        info.write_to_file(&info_file)?;
+        fs::set_permissions(&info_file, fs::Permissions::from_mode(0o600))?;
 
         println!("{}", format_mac(&dev.mac)?);
     }
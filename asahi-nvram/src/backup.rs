@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: MIT
+//! Full-device backups: a short text header followed by the raw image.
+//!
+//! ```text
+//! asahi-nvram backup 1
+//! device /dev/mtd0
+//! created 2024-05-01T12:00:00Z
+//! size 131072
+//! sha256 9f86d08...
+//!
+//! <size bytes of image>
+//! ```
+
+use sha2::{Digest, Sha256};
+
+use crate::{logging, timestamp, to_hex, Error, Result};
+
+const MAGIC: &str = "asahi-nvram backup 1\n";
+
+pub fn create(device: &str, image: &[u8]) -> Vec<u8> {
+    let mut out = format!(
+        "{MAGIC}device {device}\ncreated {}\nsize {}\nsha256 {}\n\n",
+        timestamp(),
+        image.len(),
+        to_hex(&Sha256::digest(image)),
+    )
+    .into_bytes();
+    out.extend_from_slice(image);
+    out
+}
+
+pub fn is_backup(data: &[u8]) -> bool {
+    data.starts_with(MAGIC.as_bytes())
+}
+
+/// Checks the header and returns the image it describes.
+pub fn open(data: &[u8]) -> Result<&[u8]> {
+    let rest = data.strip_prefix(MAGIC.as_bytes()).ok_or_else(|| {
+        logging::error("not an asahi-nvram backup");
+        Error::Parse
+    })?;
+    let end = rest
+        .windows(2)
+        .position(|w| w == b"\n\n")
+        .ok_or(Error::Parse)?;
+    let header = std::str::from_utf8(&rest[..end]).map_err(|_| Error::Parse)?;
+    let image = &rest[end + 2..];
+    let (mut size, mut digest) = (None, None);
+    for line in header.lines() {
+        match line.split_once(' ') {
+            Some(("size", v)) => size = v.parse::<usize>().ok(),
+            Some(("sha256", v)) => digest = Some(v),
+            _ => {}
+        }
+    }
+    if size != Some(image.len()) {
+        logging::error("backup is cut off or has trailing data");
+        return Err(Error::Parse);
+    }
+    if digest != Some(to_hex(&Sha256::digest(image)).as_str()) {
+        logging::error("backup checksum mismatch");
+        return Err(Error::Parse);
+    }
+    Ok(image)
+}
@@ -0,0 +1,290 @@
+// SPDX-License-Identifier: MIT
+//! Minimal reader for Apple binary property lists (`bplist00`).
+
+use std::fmt::Write;
+
+pub const MAGIC: &[u8] = b"bplist00";
+const MAX_DEPTH: usize = 32;
+
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Real(f64),
+    Date(f64),
+    Data(Vec<u8>),
+    String(String),
+    Uid(u64),
+    Array(Vec<Value>),
+    Dict(Vec<(Value, Value)>),
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offsets: Vec<usize>,
+    ref_size: usize,
+}
+
+fn be_uint(bytes: &[u8]) -> Option<u64> {
+    if bytes.len() > 8 {
+        return None;
+    }
+    Some(bytes.iter().fold(0, |acc, &b| (acc << 8) | b as u64))
+}
+
+impl Reader<'_> {
+    fn bytes(&self, start: usize, len: usize) -> Option<&[u8]> {
+        self.data.get(start..start.checked_add(len)?)
+    }
+    // Returns (count, offset of the payload) for variable-length objects.
+    fn length(&self, off: usize, marker: u8) -> Option<(usize, usize)> {
+        let low = marker & 0xf;
+        if low != 0xf {
+            return Some((low as usize, off.checked_add(1)?));
+        }
+        let int_marker = *self.data.get(off.checked_add(1)?)?;
+        if int_marker >> 4 != 0x1 {
+            return None;
+        }
+        let size = 1 << (int_marker & 0xf);
+        let start = off.checked_add(2)?;
+        let len = be_uint(self.bytes(start, size)?)? as usize;
+        Some((len, start.checked_add(size)?))
+    }
+    fn refs(&self, start: usize, count: usize) -> Option<Vec<usize>> {
+        (0..count)
+            .map(|i| {
+                let at = start.checked_add(i.checked_mul(self.ref_size)?)?;
+                Some(be_uint(self.bytes(at, self.ref_size)?)? as usize)
+            })
+            .collect()
+    }
+    fn object(&self, idx: usize, depth: usize) -> Option<Value> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        let off = *self.offsets.get(idx)?;
+        let marker = *self.data.get(off)?;
+        let low = marker & 0xf;
+        Some(match marker >> 4 {
+            0x0 => match marker {
+                0x08 => Value::Bool(false),
+                0x09 => Value::Bool(true),
+                _ => return None,
+            },
+            0x1 => {
+                let bytes = self.bytes(off.checked_add(1)?, 1 << low)?;
+                Value::Int(be_uint(bytes)? as i64)
+            }
+            0x2 | 0x3 => {
+                let bytes = self.bytes(off.checked_add(1)?, 1 << low)?;
+                let v = match bytes.len() {
+                    4 => f32::from_be_bytes(bytes.try_into().unwrap()) as f64,
+                    8 => f64::from_be_bytes(bytes.try_into().unwrap()),
+                    _ => return None,
+                };
+                if marker >> 4 == 0x2 {
+                    Value::Real(v)
+                } else {
+                    Value::Date(v)
+                }
+            }
+            0x4 => {
+                let (len, start) = self.length(off, marker)?;
+                Value::Data(self.bytes(start, len)?.to_vec())
+            }
+            0x5 => {
+                let (len, start) = self.length(off, marker)?;
+                Value::String(String::from_utf8_lossy(self.bytes(start, len)?).into_owned())
+            }
+            0x6 => {
+                let (len, start) = self.length(off, marker)?;
+                let units = self
+                    .bytes(start, len.checked_mul(2)?)?
+                    .chunks(2)
+                    .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                    .collect::<Vec<_>>();
+                Value::String(String::from_utf16_lossy(&units))
+            }
+            0x8 => Value::Uid(be_uint(self.bytes(off.checked_add(1)?, low as usize + 1)?)?),
+            0xa => {
+                let (len, start) = self.length(off, marker)?;
+                let items = self.refs(start, len)?;
+                Value::Array(
+                    items
+                        .into_iter()
+                        .map(|i| self.object(i, depth + 1))
+                        .collect::<Option<_>>()?,
+                )
+            }
+            0xd => {
+                let (len, start) = self.length(off, marker)?;
+                let keys = self.refs(start, len)?;
+                let vals = self.refs(start.checked_add(len.checked_mul(self.ref_size)?)?, len)?;
+                let mut entries = Vec::with_capacity(len);
+                for (k, v) in keys.into_iter().zip(vals) {
+                    entries.push((self.object(k, depth + 1)?, self.object(v, depth + 1)?));
+                }
+                Value::Dict(entries)
+            }
+            _ => return None,
+        })
+    }
+}
+
+pub fn parse(data: &[u8]) -> Option<Value> {
+    if !data.starts_with(MAGIC) || data.len() < MAGIC.len() + 32 {
+        return None;
+    }
+    let trailer = &data[data.len() - 32..];
+    let offset_size = trailer[6] as usize;
+    let ref_size = trailer[7] as usize;
+    let num_objects = be_uint(&trailer[8..16])? as usize;
+    let top = be_uint(&trailer[16..24])? as usize;
+    let table = be_uint(&trailer[24..32])? as usize;
+    if offset_size == 0 || ref_size == 0 || num_objects > data.len() {
+        return None;
+    }
+    let offsets = (0..num_objects)
+        .map(|i| {
+            let start = table.checked_add(i.checked_mul(offset_size)?)?;
+            Some(be_uint(data.get(start..start.checked_add(offset_size)?)?)? as usize)
+        })
+        .collect::<Option<_>>()?;
+    Reader {
+        data,
+        offsets,
+        ref_size,
+    }
+    .object(top, 0)
+}
+
+fn format_into(out: &mut String, v: &Value, indent: usize) {
+    let pad = "  ".repeat(indent);
+    match v {
+        Value::Bool(b) => write!(out, "{b}").unwrap(),
+        Value::Int(i) => write!(out, "{i}").unwrap(),
+        Value::Real(r) => write!(out, "{r}").unwrap(),
+        Value::Date(d) => write!(out, "date({d})").unwrap(),
+        Value::Data(d) => {
+            out.push('<');
+            for b in d {
+                write!(out, "{b:02x}").unwrap();
+            }
+            out.push('>');
+        }
+        Value::String(s) => write!(out, "{s:?}").unwrap(),
+        Value::Uid(u) => write!(out, "uid({u})").unwrap(),
+        Value::Array(items) => {
+            out.push_str("[\n");
+            for (i, item) in items.iter().enumerate() {
+                write!(out, "{pad}  {i} => ").unwrap();
+                format_into(out, item, indent + 1);
+                out.push('\n');
+            }
+            write!(out, "{pad}]").unwrap();
+        }
+        Value::Dict(entries) => {
+            out.push_str("{\n");
+            for (k, val) in entries {
+                write!(out, "{pad}  ").unwrap();
+                format_into(out, k, indent + 1);
+                out.push_str(" => ");
+                format_into(out, val, indent + 1);
+                out.push('\n');
+            }
+            write!(out, "{pad}}}").unwrap();
+        }
+    }
+}
+
+pub fn format(v: &Value) -> String {
+    let mut out = String::new();
+    format_into(&mut out, v, 0);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Lays out `objects` after the magic, the first one on top, with
+    // `offset_size`-byte offsets and one-byte object refs.
+    fn build(objects: &[&[u8]], offset_size: usize) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        let mut offsets = Vec::new();
+        for object in objects {
+            offsets.extend_from_slice(&(data.len() as u64).to_be_bytes()[8 - offset_size..]);
+            data.extend_from_slice(object);
+        }
+        let table = data.len();
+        data.extend(offsets);
+        let mut trailer = [0; 32];
+        trailer[6] = offset_size as u8;
+        trailer[7] = 1;
+        trailer[8..16].copy_from_slice(&(objects.len() as u64).to_be_bytes());
+        trailer[24..32].copy_from_slice(&(table as u64).to_be_bytes());
+        data.extend(trailer);
+        data
+    }
+
+    #[test]
+    fn parses_a_dict() {
+        let data = build(&[&[0xd1, 1, 2], &[0x51, b'a'], &[0x10, 5]], 1);
+        assert_eq!(format(&parse(&data).unwrap()), "{\n  \"a\" => 5\n}");
+    }
+
+    #[test]
+    fn parses_utf16_strings() {
+        let data = build(&[&[0x62, 0, b'h', 0x00, 0xe9]], 1);
+        assert_eq!(format(&parse(&data).unwrap()), "\"hé\"");
+    }
+
+    #[test]
+    fn rejects_truncated_data() {
+        let data = build(&[&[0xd1, 1, 2], &[0x51, b'a'], &[0x10, 5]], 1);
+        assert!(parse(&data[..MAGIC.len() + 16]).is_none());
+        assert!(parse(&data[..data.len() - 1]).is_none());
+        // A string running past the end of the data.
+        assert!(parse(&build(&[&[0x5f, 0x10, 100, b'a']], 1)).is_none());
+    }
+
+    #[test]
+    fn rejects_malicious_trailers() {
+        let mut data = build(&[&[0x09]], 1);
+        let len = data.len();
+        // The offset table lies past the end of the address space.
+        data[len - 8..].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(parse(&data).is_none());
+        // More objects than there are bytes.
+        let mut data = build(&[&[0x09]], 1);
+        data[len - 24..len - 16].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(parse(&data).is_none());
+        // The top object is not in the table.
+        let mut data = build(&[&[0x09]], 1);
+        data[len - 16..len - 8].copy_from_slice(&1u64.to_be_bytes());
+        assert!(parse(&data).is_none());
+        // An offset at the very end of the address space.
+        let mut data = build(&[&[0x09]], 8);
+        let len = data.len();
+        data[len - 40..len - 32].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(parse(&data).is_none());
+    }
+
+    #[test]
+    fn rejects_overflowing_lengths() {
+        let huge = [0x13, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+        for marker in [0x4f, 0x5f, 0x6f, 0xaf, 0xdf] {
+            let object = [&[marker][..], &huge].concat();
+            assert!(parse(&build(&[&object], 1)).is_none(), "marker {marker:#x}");
+        }
+        // Half of usize::MAX UTF-16 units, so doubling it overflows.
+        let mut object = vec![0x6f, 0x13, 0x80, 0, 0, 0, 0, 0, 0, 0];
+        object.extend_from_slice(b"ab");
+        assert!(parse(&build(&[&object], 1)).is_none());
+    }
+
+    #[test]
+    fn rejects_cycles() {
+        assert!(parse(&build(&[&[0xa1, 0]], 1)).is_none());
+    }
+}
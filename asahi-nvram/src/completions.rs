@@ -0,0 +1,90 @@
+// SPDX-License-Identifier: MIT
+//! Shell completion scripts.
+//!
+//! The static parts come from clap_complete. For bash, zsh and fish, variable
+//! arguments of read, write, delete, edit, history, copy and rename are
+//! additionally completed at runtime through the hidden `__complete`
+//! subcommand. `--list-keys` prints just the names on the device, for
+//! scripts of other shells.
+
+use std::io::{stdout, Write};
+
+use clap_complete::Shell;
+
+const BIN: &str = "asahi-nvram";
+
+const BASH: &str = r#"
+_asahi-nvram_dynamic() {
+    local cur i
+    if declare -F _get_comp_words_by_ref >/dev/null; then
+        _get_comp_words_by_ref -n : cur
+    else
+        cur="${COMP_WORDS[COMP_CWORD]}"
+    fi
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case "${COMP_WORDS[i]}" in
+            read|write|delete|edit|history|copy|rename)
+                if [[ "$cur" != -* ]]; then
+                    COMPREPLY=( $(asahi-nvram __complete "$cur" 2>/dev/null) )
+                    declare -F __ltrim_colon_completions >/dev/null && __ltrim_colon_completions "$cur"
+                    return 0
+                fi
+                break
+                ;;
+        esac
+    done
+    _asahi-nvram "$@"
+}
+complete -F _asahi-nvram_dynamic -o bashdefault -o default asahi-nvram
+"#;
+
+const ZSH: &str = r#"
+_asahi-nvram_variables() {
+    local -a names
+    names=(${(f)"$(asahi-nvram __complete "$PREFIX" 2>/dev/null)"})
+    compadd -a names
+}
+"#;
+
+const FISH: &str = r#"complete -c asahi-nvram -n "__fish_seen_subcommand_from read write delete edit history copy rename" -f -a "(asahi-nvram __complete (commandline -ct))"
+"#;
+
+pub fn print(shell: Shell) {
+    let mut script = Vec::new();
+    // The bash generator joins subcommand paths with "__" and trips over a
+    // name that starts with it.
+    let mut cmd = crate::cli().mut_subcommand("__complete", |c| c.name("complete"));
+    clap_complete::generate(shell, &mut cmd, BIN, &mut script);
+    let mut script = String::from_utf8(script).unwrap();
+    match shell {
+        Shell::Bash => script.push_str(BASH),
+        Shell::Zsh => {
+            // clap_complete leaves variable arguments without an action.
+            for arg in ["variable", "variable=value"] {
+                script = script.replace(
+                    &format!("'*::{arg}:' \\"),
+                    &format!("'*::{arg}:_asahi-nvram_variables' \\"),
+                );
+            }
+            let mut lines = Vec::new();
+            for line in script.lines() {
+                let named = ["':VARIABLE -- ", "':FROM -- ", "':TO -- "];
+                match line.strip_suffix(":' \\") {
+                    Some(start) if named.iter().any(|n| line.starts_with(n)) => {
+                        lines.push(format!("{start}:_asahi-nvram_variables' \\"));
+                    }
+                    _ => lines.push(line.to_owned()),
+                }
+            }
+            script = lines.join("\n") + "\n";
+            // The helper has to exist before the script's final call.
+            let call = format!("\n_{BIN} \"$@\"\n");
+            if let Some(pos) = script.rfind(&call) {
+                script.insert_str(pos, ZSH);
+            }
+        }
+        Shell::Fish => script.push_str(FISH),
+        _ => {}
+    }
+    stdout().write_all(script.as_bytes()).unwrap();
+}
@@ -0,0 +1,147 @@
+// SPDX-License-Identifier: MIT
+//! Defaults from /etc/asahi-nvram.toml and ~/.config/asahi-nvram/config.toml.
+//!
+//! The user file wins over the system one, command line flags win over both.
+//!
+//! ```toml
+//! device = "/dev/mtd0"
+//! state-dir = "/var/lib/asahi-nvram"
+//! labels = "/etc/asahi-nvram/labels.toml"
+//! decode = true
+//! protected = ["common:boot-args"]
+//!
+//! [aliases]
+//! ba = "common:boot-args"
+//!
+//! [hooks]
+//! pre-write = ["/usr/local/bin/check-nvram"]
+//! post-write = ["logger -t asahi-nvram written"]
+//!
+//! [free-space]
+//! warn = 80
+//! fail = 95
+//! ```
+
+use std::{collections::HashMap, env, path::PathBuf};
+
+use toml::{Table, Value};
+
+use crate::{hooks::Hooks, logging, space::Thresholds};
+
+const SYSTEM_CONFIG: &str = "/etc/asahi-nvram.toml";
+
+#[derive(Default)]
+pub struct Config {
+    pub device: Option<String>,
+    pub state_dir: Option<String>,
+    pub labels: Option<String>,
+    pub decode: bool,
+    pub protected: Vec<String>,
+    pub aliases: HashMap<String, String>,
+    pub hooks: Hooks,
+    pub free_space: Thresholds,
+}
+
+fn strings(a: &[Value]) -> Vec<String> {
+    a.iter()
+        .filter_map(|v| v.as_str().map(str::to_owned))
+        .collect()
+}
+
+fn user_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("asahi-nvram"))
+}
+
+impl Config {
+    pub fn load() -> Config {
+        let mut config = Config::default();
+        let paths = [
+            Some(PathBuf::from(SYSTEM_CONFIG)),
+            user_dir().map(|d| d.join("config.toml")),
+        ];
+        for path in paths.into_iter().flatten() {
+            let text = match std::fs::read_to_string(&path) {
+                Ok(t) => t,
+                Err(_) => continue,
+            };
+            match text.parse::<Table>() {
+                Ok(table) => config.merge(&table),
+                Err(e) => logging::warn(&format!("ignoring {}: {e}", path.display())),
+            }
+        }
+        config
+    }
+
+    fn merge(&mut self, table: &Table) {
+        for (key, value) in table {
+            match (key.as_str(), value) {
+                ("device", Value::String(s)) => self.device = Some(s.clone()),
+                ("state-dir", Value::String(s)) => self.state_dir = Some(s.clone()),
+                ("labels", Value::String(s)) => self.labels = Some(s.clone()),
+                ("decode", Value::Boolean(b)) => self.decode = *b,
+                ("protected", Value::Array(a)) => self.protected = strings(a),
+                ("aliases", Value::Table(t)) => {
+                    for (alias, target) in t {
+                        if let Some(target) = target.as_str() {
+                            self.aliases.insert(alias.clone(), target.to_owned());
+                        }
+                    }
+                }
+                ("hooks", Value::Table(t)) => {
+                    for (hook, commands) in t {
+                        match (hook.as_str(), commands) {
+                            ("pre-write", Value::Array(a)) => self.hooks.pre_write = strings(a),
+                            ("post-write", Value::Array(a)) => self.hooks.post_write = strings(a),
+                            _ => logging::warn(&format!("unknown hook {hook}")),
+                        }
+                    }
+                }
+                ("free-space", Value::Table(t)) => {
+                    for (level, percent) in t {
+                        let percent = percent.as_integer().and_then(|p| u64::try_from(p).ok());
+                        match level.as_str() {
+                            "warn" => self.free_space.warn = percent,
+                            "fail" => self.free_space.fail = percent,
+                            _ => logging::warn(&format!("unknown free-space level {level}")),
+                        }
+                    }
+                }
+                _ => logging::warn(&format!("unknown config key {key}")),
+            }
+        }
+    }
+
+    /// Resolves an alias to the `section:name` it stands for.
+    pub fn expand<'a>(&'a self, name: &'a str) -> &'a str {
+        self.aliases.get(name).map_or(name, |s| s.as_str())
+    }
+
+    /// Where variable labels are kept, ~/.config/asahi-nvram/labels.toml by default.
+    pub fn labels_path(&self) -> Option<PathBuf> {
+        match &self.labels {
+            Some(path) => Some(PathBuf::from(path)),
+            None => Some(user_dir()?.join("labels.toml")),
+        }
+    }
+
+    /// Where `stage` keeps changes until `commit`.
+    pub fn staging_path(&self) -> Option<PathBuf> {
+        Some(user_dir()?.join("staged.json"))
+    }
+
+    /// Where the shell keeps its history.
+    #[cfg(feature = "shell")]
+    pub fn history_path(&self) -> Option<PathBuf> {
+        Some(user_dir()?.join("history"))
+    }
+
+    pub fn is_protected(&self, section: &str, name: &str) -> bool {
+        self.protected
+            .iter()
+            .any(|p| p.split_once(':') == Some((section, name)))
+    }
+}
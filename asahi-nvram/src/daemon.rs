@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: MIT
+//! Device operations shared by the long-running service modes.
+
+use std::sync::Mutex;
+
+use apple_nvram::{Nvram, Variable};
+
+use crate::{
+    device::{self, Device},
+    diff::{self, Snapshot},
+    part_by_name, write_nvram, Error, Result,
+};
+
+pub struct Store {
+    device: String,
+    // Serializes read-modify-write cycles between concurrent clients.
+    lock: Mutex<()>,
+}
+
+impl Store {
+    pub fn new(device: &str) -> Store {
+        Store {
+            device: device.to_owned(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    fn read(&self) -> Result<Vec<u8>> {
+        device::read(&self.device)
+    }
+
+    pub fn list(&self) -> Result<Vec<String>> {
+        let data = self.read()?;
+        let nv = Nvram::parse(&data)?;
+        let part = &nv.partitions[nv.active];
+        let mut names = Vec::new();
+        for (section, values) in [
+            ("common", &part.common.values),
+            ("system", &part.system.values),
+        ] {
+            for key in values.keys() {
+                names.push(format!("{section}:{}", String::from_utf8_lossy(key)));
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Vec<u8>> {
+        let (part, key) = name.split_once(':').ok_or(Error::MissingPartitionName)?;
+        let data = self.read()?;
+        let mut nv = Nvram::parse(&data)?;
+        let var = part_by_name(part, &mut nv)?
+            .values
+            .get(key.as_bytes())
+            .ok_or(Error::VariableNotFound)?;
+        Ok(var.bytes())
+    }
+
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        let data = self.read()?;
+        Ok(diff::snapshot(&Nvram::parse(&data)?))
+    }
+
+    pub fn set(&self, name: &str, value: &[u8]) -> Result<()> {
+        self.apply(&[(name, Some(value))])
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        self.apply(&[(name, None)])
+    }
+
+    /// Applies several sets (`Some`) and deletes (`None`) in a single write.
+    pub fn apply(&self, changes: &[(&str, Option<&[u8]>)]) -> Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut file = Device::open(&self.device, true)?;
+        let data = file.read()?;
+        let mut nv = Nvram::parse(&data)?;
+        nv.prepare_for_write();
+        for &(name, value) in changes {
+            let (part, key) = name.split_once(':').ok_or(Error::MissingPartitionName)?;
+            let values = &mut part_by_name(part, &mut nv)?.values;
+            match value {
+                Some(value) => {
+                    values.insert(key.as_bytes(), Variable::from_bytes(key.as_bytes(), value));
+                }
+                None => {
+                    values
+                        .shift_remove(key.as_bytes())
+                        .ok_or(Error::VariableNotFound)?;
+                }
+            }
+        }
+        write_nvram(&mut file, &nv)
+    }
+}
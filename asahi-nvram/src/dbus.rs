@@ -0,0 +1,184 @@
+// SPDX-License-Identifier: MIT
+//! `org.asahilinux.Nvram1` on the system bus.
+//!
+//! Every method call is checked against polkit: listing and reading need
+//! `org.asahilinux.nvram.read`, modifications need `org.asahilinux.nvram.write`.
+//!
+//! [`Client`] is the other end, used by the CLI when it cannot open the
+//! device itself.
+
+use std::collections::HashMap;
+
+use zbus::{
+    fdo, interface, message::Header, object_server::SignalEmitter, zvariant::Value, Connection,
+};
+
+use crate::daemon::Store;
+
+pub const BUS_NAME: &str = "org.asahilinux.Nvram";
+pub const OBJECT_PATH: &str = "/org/asahilinux/Nvram";
+
+const INTERFACE: &str = "org.asahilinux.Nvram1";
+const ACTION_READ: &str = "org.asahilinux.nvram.read";
+const ACTION_WRITE: &str = "org.asahilinux.nvram.write";
+
+struct Nvram {
+    store: Store,
+}
+
+async fn authorize(conn: &Connection, header: &Header<'_>, action: &str) -> fdo::Result<()> {
+    let sender = header
+        .sender()
+        .ok_or_else(|| fdo::Error::AccessDenied("unknown sender".into()))?;
+    let subject_details = HashMap::from([("name", Value::from(sender.as_str()))]);
+    let details = HashMap::<&str, &str>::new();
+    // Flag 1 lets polkit start an authentication agent dialog.
+    let reply = conn
+        .call_method(
+            Some("org.freedesktop.PolicyKit1"),
+            "/org/freedesktop/PolicyKit1/Authority",
+            Some("org.freedesktop.PolicyKit1.Authority"),
+            "CheckAuthorization",
+            &(
+                ("system-bus-name", subject_details),
+                action,
+                details,
+                1u32,
+                "",
+            ),
+        )
+        .await?;
+    let (authorized, _challenge, _details): (bool, bool, HashMap<String, String>) =
+        reply.body().deserialize()?;
+    if authorized {
+        Ok(())
+    } else {
+        Err(fdo::Error::AccessDenied(format!(
+            "not authorized for {action}"
+        )))
+    }
+}
+
+fn failed(e: crate::Error) -> fdo::Error {
+    fdo::Error::Failed(format!("{e}"))
+}
+
+#[interface(name = "org.asahilinux.Nvram1")]
+impl Nvram {
+    async fn list(
+        &self,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> fdo::Result<Vec<String>> {
+        authorize(conn, &header, ACTION_READ).await?;
+        self.store.list().map_err(failed)
+    }
+
+    async fn get(
+        &self,
+        name: &str,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> fdo::Result<Vec<u8>> {
+        authorize(conn, &header, ACTION_READ).await?;
+        self.store.get(name).map_err(failed)
+    }
+
+    async fn set(
+        &self,
+        name: &str,
+        value: Vec<u8>,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<()> {
+        authorize(conn, &header, ACTION_WRITE).await?;
+        self.store.set(name, &value).map_err(failed)?;
+        Self::changed(&emitter, name).await?;
+        Ok(())
+    }
+
+    async fn delete(
+        &self,
+        name: &str,
+        #[zbus(connection)] conn: &Connection,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> fdo::Result<()> {
+        authorize(conn, &header, ACTION_WRITE).await?;
+        self.store.delete(name).map_err(failed)?;
+        Self::changed(&emitter, name).await?;
+        Ok(())
+    }
+
+    #[zbus(signal)]
+    async fn changed(emitter: &SignalEmitter<'_>, name: &str) -> zbus::Result<()>;
+}
+
+pub fn serve(device: &str) -> zbus::Result<()> {
+    let _conn = zbus::blocking::connection::Builder::system()?
+        .name(BUS_NAME)?
+        .serve_at(
+            OBJECT_PATH,
+            Nvram {
+                store: Store::new(device),
+            },
+        )?
+        .build()?;
+    loop {
+        std::thread::park();
+    }
+}
+
+pub struct Client(zbus::blocking::Connection);
+
+impl Client {
+    /// Connects to the service, failing if it is not running.
+    pub fn connect() -> zbus::Result<Client> {
+        let conn = zbus::blocking::Connection::system()?;
+        let bus = zbus::blocking::fdo::DBusProxy::new(&conn)?;
+        if !bus.name_has_owner(BUS_NAME.try_into()?)? {
+            return Err(zbus::Error::Failure(format!("{BUS_NAME} is not running")));
+        }
+        Ok(Client(conn))
+    }
+
+    pub fn list(&self) -> zbus::Result<Vec<String>> {
+        self.0
+            .call_method(Some(BUS_NAME), OBJECT_PATH, Some(INTERFACE), "List", &())?
+            .body()
+            .deserialize()
+    }
+
+    pub fn get(&self, name: &str) -> zbus::Result<Vec<u8>> {
+        self.0
+            .call_method(
+                Some(BUS_NAME),
+                OBJECT_PATH,
+                Some(INTERFACE),
+                "Get",
+                &(name,),
+            )?
+            .body()
+            .deserialize()
+    }
+
+    pub fn set(&self, name: &str, value: &[u8]) -> zbus::Result<()> {
+        let body = (name, value);
+        self.0
+            .call_method(Some(BUS_NAME), OBJECT_PATH, Some(INTERFACE), "Set", &body)
+            .map(drop)
+    }
+
+    pub fn delete(&self, name: &str) -> zbus::Result<()> {
+        self.0
+            .call_method(
+                Some(BUS_NAME),
+                OBJECT_PATH,
+                Some(INTERFACE),
+                "Delete",
+                &(name,),
+            )
+            .map(drop)
+    }
+}
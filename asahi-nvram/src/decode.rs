@@ -0,0 +1,204 @@
+// SPDX-License-Identifier: MIT
+//! Human-readable renderings of well-known variables, used by `read --decode`.
+//!
+//! Besides the built-in decoders, executables named `<section>:<key>` in the
+//! plugin directory are consulted. They are run as `<plugin> decode` with the
+//! raw value on stdin and print the rendering on stdout; `<plugin> encode`
+//! does the reverse for `write --encode`.
+
+use crate::{bplist, devpath, logging};
+use std::{
+    env,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+const DEFAULT_PLUGIN_DIR: &str = "/etc/asahi-nvram/decoders";
+
+type Decoder = fn(&[u8]) -> Option<String>;
+
+// (section, key, tag, decoder)
+const DECODERS: &[(&str, &str, &str, Decoder)] = &[
+    ("system", "preferred-networks", "networks", decode_networks),
+    ("system", "current-network", "network", decode_networks),
+    ("common", "aapl,panic-info", "panic", decode_panic_info),
+    ("system", "boot-volume", "boot-volume", decode_boot_volume),
+    (
+        "system",
+        "alt-boot-volume",
+        "boot-volume",
+        decode_boot_volume,
+    ),
+    (
+        "system",
+        "upgrade-boot-volume",
+        "boot-volume",
+        decode_boot_volume,
+    ),
+];
+
+pub struct Decoded {
+    pub tag: &'static str,
+    pub text: String,
+}
+
+pub fn decode(section: &str, key: &[u8], value: &[u8]) -> Option<Decoded> {
+    for &(sec, name, tag, decoder) in DECODERS {
+        if sec == section && name.as_bytes() == key {
+            return decoder(value).map(|text| Decoded { tag, text });
+        }
+    }
+    if let Some(text) = run_plugin(section, key, "decode", value) {
+        let text = String::from_utf8_lossy(&text).trim_end().to_owned();
+        return Some(Decoded {
+            tag: "plugin",
+            text,
+        });
+    }
+    if value.starts_with(bplist::MAGIC) {
+        return bplist::parse(value).map(|v| Decoded {
+            tag: "bplist",
+            text: bplist::format(&v),
+        });
+    }
+    if let Some(text) = devpath::decode(value) {
+        return Some(Decoded {
+            tag: "devpath",
+            text,
+        });
+    }
+    utf16_text(value).map(|text| Decoded { tag: "utf16", text })
+}
+
+// Only accepts values that are almost certainly text: mostly ASCII-range
+// code units (so every other byte is NUL) and no control characters.
+fn utf16_text(data: &[u8]) -> Option<String> {
+    if data.len() < 4 || !data.len().is_multiple_of(2) {
+        return None;
+    }
+    let mut units = data
+        .chunks(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect::<Vec<_>>();
+    while units.last() == Some(&0) {
+        units.pop();
+    }
+    if units.len() < 2 || units.iter().filter(|&&u| u < 0x100).count() * 4 < units.len() * 3 {
+        return None;
+    }
+    let text = String::from_utf16(&units).ok()?;
+    if text.chars().any(|c| c.is_control()) {
+        return None;
+    }
+    Some(text)
+}
+
+/// Runs the `encode` direction of a plugin, if one handles this variable.
+pub fn encode(section: &str, key: &[u8], text: &[u8]) -> Option<Vec<u8>> {
+    run_plugin(section, key, "encode", text)
+}
+
+fn plugin_path(section: &str, key: &[u8]) -> Option<PathBuf> {
+    let key = std::str::from_utf8(key).ok()?;
+    if key.contains('/') {
+        return None;
+    }
+    let dir = env::var_os("ASAHI_NVRAM_PLUGIN_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_PLUGIN_DIR));
+    let path = dir.join(format!("{section}:{key}"));
+    path.is_file().then_some(path)
+}
+
+fn run_plugin(section: &str, key: &[u8], direction: &str, input: &[u8]) -> Option<Vec<u8>> {
+    let path = plugin_path(section, key)?;
+    let mut child = Command::new(&path)
+        .arg(direction)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .ok()?;
+    child.stdin.take().unwrap().write_all(input).ok()?;
+    let out = child.wait_with_output().ok()?;
+    if !out.status.success() {
+        logging::warn(&format!("{}: {direction} failed", path.display()));
+        return None;
+    }
+    Some(out.stdout)
+}
+
+// <partition type>:<partition uuid>:<volume group uuid>
+fn decode_boot_volume(data: &[u8]) -> Option<String> {
+    let s = std::str::from_utf8(data).ok()?;
+    let mut fields = s.split(':');
+    let (_, part, vg) = (fields.next()?, fields.next()?, fields.next()?);
+    if fields.next().is_some() {
+        return None;
+    }
+    Some(format!("partition {part}, volume group {vg}"))
+}
+
+const NETWORK_CHUNK_LEN: usize = 0xc0;
+
+fn decode_networks(data: &[u8]) -> Option<String> {
+    if data.is_empty() || !data.len().is_multiple_of(NETWORK_CHUNK_LEN) {
+        return None;
+    }
+    let mut lines = Vec::new();
+    for chunk in data.chunks(NETWORK_CHUNK_LEN) {
+        let security = u32::from_le_bytes(chunk[0x8..0xc].try_into().unwrap());
+        let ssid_len = u32::from_le_bytes(chunk[0xc..0x10].try_into().unwrap()) as usize;
+        if ssid_len > 32 {
+            return None;
+        }
+        let ssid = String::from_utf8_lossy(&chunk[0x10..0x10 + ssid_len]);
+        let security = if security != 0 { "wpa-psk" } else { "open" };
+        lines.push(format!("ssid={ssid} security={security}"));
+    }
+    Some(lines.join("\n"))
+}
+
+pub const PANIC_INFO_KEY: &str = "aapl,panic-info";
+// Large panic logs get split across numbered continuation variables.
+pub const PANIC_INFO_PREFIX: &str = "AAPL,PanicInfo";
+
+/// Reverses xnu's packA(): every 7 bytes hold eight 7-bit characters.
+pub fn unpack_panic_info(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() * 8 / 7 + 8);
+    for chunk in data.chunks(7) {
+        let mut bytes = [0; 8];
+        bytes[..chunk.len()].copy_from_slice(chunk);
+        let packed = u64::from_le_bytes(bytes);
+        for i in 0..(chunk.len() * 8 / 7) {
+            out.push(((packed >> (7 * i)) & 0x7f) as u8);
+        }
+    }
+    while out.last() == Some(&0) {
+        out.pop();
+    }
+    out
+}
+
+fn is_text(data: &[u8]) -> bool {
+    !data.is_empty()
+        && data
+            .iter()
+            .all(|&c| c == b'\n' || c == b'\t' || c == b'\r' || (0x20..0x7f).contains(&c))
+}
+
+/// Returns the panic log text, whether it was stored packed or verbatim.
+pub fn panic_log(data: &[u8]) -> Option<String> {
+    let unpacked = unpack_panic_info(data);
+    if is_text(&unpacked) {
+        Some(String::from_utf8_lossy(&unpacked).into_owned())
+    } else if is_text(data) {
+        Some(String::from_utf8_lossy(data).into_owned())
+    } else {
+        None
+    }
+}
+
+fn decode_panic_info(data: &[u8]) -> Option<String> {
+    panic_log(data).map(|s| s.trim_end().to_owned())
+}
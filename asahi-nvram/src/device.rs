@@ -0,0 +1,337 @@
+// SPDX-License-Identifier: MIT
+//! Where the variables are kept: an MTD device (or an image of one), the
+//! kernel's nvmem interface as `nvmem:<name>`, or a directory given as
+//! `dir:<path>`.
+//!
+//! Files are read and written through apple_nvram's [`NvramStore`], which
+//! takes care of erasing MTD devices.
+//!
+//! Files are flock()ed while open, exclusively when opened for writing, so
+//! two writers (asahi-bless included) cannot interleave their generations.
+//!
+//! A directory holds `common/` and `system/`, with one file per variable
+//! containing its raw value, so the whole CLI can be tried out without a
+//! Mac. It is presented to the rest of the tool as a freshly formatted store.
+//!
+//! `dbus:` goes through the D-Bus service instead, for users who cannot open
+//! the device, and is presented the same way.
+
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use apple_nvram::{find_mtd, store, Layout, Nvram, NvramStore, Partition, Variable};
+
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+
+use crate::{logging, Error, Result};
+
+pub const DIR_PREFIX: &str = "dir:";
+pub const NVMEM_PREFIX: &str = "nvmem:";
+#[cfg(feature = "dbus")]
+pub const BUS_SPEC: &str = "dbus:";
+
+const MTD_DEFAULT: &str = "/dev/mtd0";
+const NVMEM_DEVICES: &str = "/sys/bus/nvmem/devices";
+
+pub enum Device {
+    /// An MTD device, an nvmem provider or an image file.
+    Store(Box<dyn NvramStore>),
+    Dir(PathBuf),
+    #[cfg(feature = "dbus")]
+    Bus(crate::dbus::Client),
+}
+
+fn nvmem_path(name: &str) -> PathBuf {
+    Path::new(NVMEM_DEVICES).join(name).join("nvmem")
+}
+
+/// The MTD partition named nvram, or /dev/mtd0 if there is one, otherwise an
+/// nvmem provider with `nvram` in its name.
+pub fn default_spec() -> String {
+    if let Some(path) = find_mtd() {
+        return path.display().to_string();
+    }
+    if Path::new(MTD_DEFAULT).exists() {
+        return MTD_DEFAULT.to_owned();
+    }
+    let found = fs::read_dir(NVMEM_DEVICES).ok().and_then(|entries| {
+        entries
+            .filter_map(|e| e.ok()?.file_name().into_string().ok())
+            .find(|name| name.to_ascii_lowercase().contains("nvram"))
+    });
+    match found {
+        Some(name) => format!("{NVMEM_PREFIX}{name}"),
+        None => MTD_DEFAULT.to_owned(),
+    }
+}
+
+/// Whether `path` is a plain file rather than a device node.
+pub fn is_image(path: &Path) -> bool {
+    fs::metadata(path).is_ok_and(|m| m.is_file())
+}
+
+/// Whether `spec` is a device or file this user may not open as asked.
+#[cfg(feature = "dbus")]
+pub fn denied(spec: &str, write: bool) -> bool {
+    if spec.starts_with(DIR_PREFIX) {
+        return false;
+    }
+    let path = match spec.strip_prefix(NVMEM_PREFIX) {
+        Some(name) => nvmem_path(name),
+        None => PathBuf::from(spec),
+    };
+    let opened = OpenOptions::new().read(true).write(write).open(path);
+    matches!(opened, Err(e) if e.kind() == io::ErrorKind::PermissionDenied)
+}
+
+#[cfg(feature = "dbus")]
+fn bus_error(e: zbus::Error) -> Error {
+    logging::error(&format!("D-Bus service: {e}"));
+    Error::Io
+}
+
+// Says why `path` could not be opened, with a hint for the common case.
+fn open_error(path: &Path, e: io::Error) -> Error {
+    let hint = match e.kind() {
+        io::ErrorKind::PermissionDenied => " - are you root?",
+        _ => "",
+    };
+    logging::error(&format!("cannot open {}: {e}{hint}", path.display()));
+    Error::Io
+}
+
+fn open_file(path: &Path, write: bool) -> Result<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(write)
+        .open(path)
+        .map_err(|e| open_error(path, e))?;
+    let locked = match write {
+        true => file.try_lock(),
+        false => file.try_lock_shared(),
+    };
+    match locked {
+        Ok(()) => Ok(file),
+        Err(TryLockError::WouldBlock) => {
+            logging::error(&format!("{} is in use by another process", path.display()));
+            Err(Error::Locked)
+        }
+        Err(TryLockError::Error(_)) => Err(Error::Io),
+    }
+}
+
+impl Device {
+    /// Opens `spec` for reading, and for writing too only if `write` is set,
+    /// so inspecting needs no more than read permission.
+    pub fn open(spec: &str, write: bool) -> Result<Device> {
+        if let Some(dir) = spec.strip_prefix(DIR_PREFIX) {
+            return Ok(Device::Dir(PathBuf::from(dir)));
+        }
+        #[cfg(feature = "dbus")]
+        if spec == BUS_SPEC {
+            let client = crate::dbus::Client::connect().map_err(bus_error)?;
+            return Ok(Device::Bus(client));
+        }
+        let path = match spec.strip_prefix(NVMEM_PREFIX) {
+            Some(name) => nvmem_path(name),
+            None => PathBuf::from(spec),
+        };
+        Ok(Device::Store(store::from_file(open_file(&path, write)?)))
+    }
+
+    pub fn read(&mut self) -> Result<Vec<u8>> {
+        match self {
+            Device::Store(store) => Ok(store.read()?),
+            Device::Dir(dir) => read_dir_image(dir),
+            #[cfg(feature = "dbus")]
+            Device::Bus(client) => read_bus_image(client),
+        }
+    }
+
+    /// Stores the active partition, returning the size of the serialized image.
+    pub fn write(&mut self, nv: &Nvram) -> Result<usize> {
+        match self {
+            Device::Store(store) => Ok(store.write_nvram(nv)?),
+            Device::Dir(dir) => {
+                write_dir(dir, nv)?;
+                Ok(nv.serialize()?.len())
+            }
+            #[cfg(feature = "dbus")]
+            Device::Bus(client) => {
+                write_bus(client, nv)?;
+                Ok(nv.serialize()?.len())
+            }
+        }
+    }
+
+    /// Replaces the whole store with `data`, which must parse.
+    pub fn write_image(&mut self, data: &[u8]) -> Result<()> {
+        match self {
+            Device::Store(store) => Ok(store.write(data)?),
+            Device::Dir(dir) => write_dir(dir, &Nvram::parse(data)?),
+            #[cfg(feature = "dbus")]
+            Device::Bus(client) => write_bus(client, &Nvram::parse(data)?),
+        }
+    }
+}
+
+/// An inotify instance reporting writes to `spec`, if it is an image file or
+/// a directory. Devices change without telling inotify, so they get `None`.
+pub fn watcher(spec: &str) -> Option<Inotify> {
+    let flags = AddWatchFlags::IN_CLOSE_WRITE
+        | AddWatchFlags::IN_MODIFY
+        | AddWatchFlags::IN_MOVED_TO
+        | AddWatchFlags::IN_CREATE
+        | AddWatchFlags::IN_DELETE;
+    let inotify = Inotify::init(InitFlags::IN_CLOEXEC | InitFlags::IN_NONBLOCK).ok()?;
+    if let Some(dir) = spec.strip_prefix(DIR_PREFIX) {
+        let dir = Path::new(dir);
+        for path in [dir.to_path_buf(), dir.join("common"), dir.join("system")] {
+            // The sections are created on the first write.
+            let _ = inotify.add_watch(&path, flags);
+        }
+        return Some(inotify);
+    }
+    if spec.starts_with(NVMEM_PREFIX) || !fs::metadata(spec).ok()?.is_file() {
+        return None;
+    }
+    inotify.add_watch(spec, flags).ok()?;
+    Some(inotify)
+}
+
+/// Reads the current contents without opening the device for writing.
+pub fn read(spec: &str) -> Result<Vec<u8>> {
+    if let Some(dir) = spec.strip_prefix(DIR_PREFIX) {
+        return read_dir_image(Path::new(dir));
+    }
+    #[cfg(feature = "dbus")]
+    if spec == BUS_SPEC {
+        return Device::open(spec, false)?.read();
+    }
+    let path = match spec.strip_prefix(NVMEM_PREFIX) {
+        Some(name) => nvmem_path(name),
+        None => PathBuf::from(spec),
+    };
+    // Waits for a writer to finish rather than failing, this is used by
+    // long-running readers.
+    let mut file = File::open(&path).map_err(|e| open_error(&path, e))?;
+    file.lock_shared().map_err(|_| Error::Io)?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).map_err(|_| Error::Io)?;
+    Ok(data)
+}
+
+// Names of the variable files in a section directory, sorted.
+fn keys(dir: &Path) -> Result<Vec<String>> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(_) => return Err(Error::Io),
+    };
+    let mut keys = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|_| Error::Io)?;
+        if entry.file_type().map_err(|_| Error::Io)?.is_file() {
+            keys.push(entry.file_name().into_string().map_err(|_| Error::Parse)?);
+        }
+    }
+    keys.sort();
+    Ok(keys)
+}
+
+// (key, raw value) of every variable in a section directory.
+fn read_section_dir(dir: &Path) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    keys(dir)?
+        .into_iter()
+        .map(|key| {
+            let value = fs::read(dir.join(&key)).map_err(|_| Error::Io)?;
+            Ok((key.into_bytes(), value))
+        })
+        .collect()
+}
+
+// Both banks get the same variables at generation 1, laid out like a real
+// store so that space limits behave the same.
+fn fresh_image(common: &[(Vec<u8>, Vec<u8>)], system: &[(Vec<u8>, Vec<u8>)]) -> Result<Vec<u8>> {
+    let mut part = Partition::empty();
+    for (section, vars) in [(&mut part.common, common), (&mut part.system, system)] {
+        for (k, v) in vars {
+            section.values.insert(k, Variable::from_bytes(k, v));
+        }
+    }
+    Ok(Nvram {
+        partitions: [part.clone(), part],
+        active: 1,
+        layout: Layout::Banked,
+    }
+    .serialize()?)
+}
+
+fn read_dir_image(dir: &Path) -> Result<Vec<u8>> {
+    let common = read_section_dir(&dir.join("common"))?;
+    let system = read_section_dir(&dir.join("system"))?;
+    fresh_image(&common, &system)
+}
+
+#[cfg(feature = "dbus")]
+fn read_bus_image(client: &crate::dbus::Client) -> Result<Vec<u8>> {
+    let (mut common, mut system) = (Vec::new(), Vec::new());
+    for name in client.list().map_err(bus_error)? {
+        let value = client.get(&name).map_err(bus_error)?;
+        let (section, key) = name.split_once(':').ok_or(Error::Parse)?;
+        let vars = match section {
+            "common" => &mut common,
+            _ => &mut system,
+        };
+        vars.push((key.as_bytes().to_vec(), value));
+    }
+    fresh_image(&common, &system)
+}
+
+// Sends only what differs from the service's current state.
+#[cfg(feature = "dbus")]
+fn write_bus(client: &crate::dbus::Client, nv: &Nvram) -> Result<()> {
+    use crate::diff::{self, Change};
+
+    let old = diff::snapshot(&Nvram::parse(&read_bus_image(client)?)?);
+    let new = diff::snapshot(nv);
+    for ((section, key), change) in diff::changes(&old, &new) {
+        let name = format!(
+            "{section}:{}",
+            std::str::from_utf8(key).map_err(|_| Error::InvalidName)?
+        );
+        match change {
+            Change::Removed(_) => client.delete(&name),
+            Change::Added(value) | Change::Changed(_, value) => client.set(&name, value),
+        }
+        .map_err(bus_error)?;
+    }
+    Ok(())
+}
+
+fn write_dir(dir: &Path, nv: &Nvram) -> Result<()> {
+    let part = &nv.partitions[nv.active];
+    for (name, section) in [("common", &part.common), ("system", &part.system)] {
+        let sdir = dir.join(name);
+        fs::create_dir_all(&sdir).map_err(|_| Error::Io)?;
+        let mut stale = keys(&sdir)?;
+        for var in section.values.values() {
+            let key = std::str::from_utf8(var.key).map_err(|_| Error::InvalidName)?;
+            if key.contains('/') || key == "." || key == ".." {
+                return Err(Error::InvalidName);
+            }
+            let value = var.bytes();
+            if fs::read(sdir.join(key)).ok().as_ref() != Some(&value) {
+                fs::write(sdir.join(key), value).map_err(|_| Error::Io)?;
+            }
+            stale.retain(|k| k != key);
+        }
+        for key in stale {
+            fs::remove_file(sdir.join(key)).map_err(|_| Error::Io)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,271 @@
+// SPDX-License-Identifier: MIT
+//! EFI device paths in the UEFI text form, e.g.
+//! `PciRoot(0x0)/Pci(0x1c,0x0)/NVMe(0x1,00-00-00-00-00-00-00-00)/HD(...)`.
+//!
+//! Nodes without a name of their own are shown as `Path(type,subtype,hex)`,
+//! so every path that decodes encodes back to the same bytes.
+
+use crate::{from_hex, to_hex};
+
+const END: [u8; 4] = [0x7f, 0xff, 4, 0];
+const PNP_PCI_ROOT: u32 = 0x0a0341d0;
+
+fn u16_at(data: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(data[at..at + 2].try_into().unwrap())
+}
+
+fn u32_at(data: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn u64_at(data: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+// EFI GUIDs keep their first three fields little-endian.
+fn guid(d: &[u8]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{}-{}",
+        u32_at(d, 0),
+        u16_at(d, 4),
+        u16_at(d, 6),
+        to_hex(&d[8..10]),
+        to_hex(&d[10..16])
+    )
+    .to_ascii_uppercase()
+}
+
+fn parse_guid(text: &str) -> Option<Vec<u8>> {
+    let f = text.split('-').collect::<Vec<_>>();
+    let [a, b, c, d, e] = f[..] else {
+        return None;
+    };
+    if (a.len(), b.len(), c.len(), d.len(), e.len()) != (8, 4, 4, 4, 12) {
+        return None;
+    }
+    let mut ret = u32::from_str_radix(a, 16).ok()?.to_le_bytes().to_vec();
+    ret.extend(u16::from_str_radix(b, 16).ok()?.to_le_bytes());
+    ret.extend(u16::from_str_radix(c, 16).ok()?.to_le_bytes());
+    ret.extend(from_hex(d)?);
+    ret.extend(from_hex(e)?);
+    Some(ret)
+}
+
+// \EFI\BOOT\BOOTAA64.EFI and the like, as long as it cannot be mistaken for
+// another node when read back.
+fn file_path(data: &[u8]) -> Option<String> {
+    if !data.len().is_multiple_of(2) {
+        return None;
+    }
+    let units = data
+        .chunks(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect::<Vec<_>>();
+    let (0, units) = units.split_last()? else {
+        return None;
+    };
+    let text = String::from_utf16(units).ok()?;
+    let clean = !text.is_empty()
+        && !text.contains(['/', '(', ')', '\0'])
+        && !text.chars().any(|c| c.is_control());
+    clean.then_some(text)
+}
+
+fn node(ty: u8, sub: u8, d: &[u8]) -> String {
+    let named = match (ty, sub, d.len()) {
+        (1, 1, 2) => Some(format!("Pci({:#x},{:#x})", d[1], d[0])),
+        (2, 1, 8) if u32_at(d, 0) == PNP_PCI_ROOT => Some(format!("PciRoot({:#x})", u32_at(d, 4))),
+        (2, 1, 8) => Some(format!("Acpi({:#x},{:#x})", u32_at(d, 0), u32_at(d, 4))),
+        (3, 5, 2) => Some(format!("USB({:#x},{:#x})", d[0], d[1])),
+        (3, 18, 6) => Some(format!(
+            "Sata({:#x},{:#x},{:#x})",
+            u16_at(d, 0),
+            u16_at(d, 2),
+            u16_at(d, 4)
+        )),
+        (3, 23, 12) => {
+            let eui = d[4..]
+                .iter()
+                .map(|b| format!("{b:02X}"))
+                .collect::<Vec<_>>();
+            Some(format!("NVMe({:#x},{})", u32_at(d, 0), eui.join("-")))
+        }
+        // Only the common combinations, GPT with a GUID and MBR with an id.
+        (4, 1, 38) => match (d[36], d[37]) {
+            (2, 2) => Some(format!(
+                "HD({},GPT,{},{:#x},{:#x})",
+                u32_at(d, 0),
+                guid(&d[20..36]),
+                u64_at(d, 4),
+                u64_at(d, 12)
+            )),
+            (1, 1) if d[24..36].iter().all(|&b| b == 0) => Some(format!(
+                "HD({},MBR,{:#010x},{:#x},{:#x})",
+                u32_at(d, 0),
+                u32_at(d, 20),
+                u64_at(d, 4),
+                u64_at(d, 12)
+            )),
+            _ => None,
+        },
+        (4, 4, _) => file_path(d),
+        _ => None,
+    };
+    named.unwrap_or_else(|| format!("Path({ty},{sub},{})", to_hex(d)))
+}
+
+/// `data` as a device path, or `None` unless it is exactly one well-formed
+/// path up to its end node.
+pub fn decode(data: &[u8]) -> Option<String> {
+    let mut nodes = Vec::new();
+    let mut rest = data;
+    loop {
+        if rest.len() < 4 {
+            return None;
+        }
+        let len = u16_at(rest, 2) as usize;
+        if len < 4 || len > rest.len() {
+            return None;
+        }
+        if rest[..4] == END {
+            return (len == rest.len() && !nodes.is_empty()).then(|| nodes.join("/"));
+        }
+        nodes.push(node(rest[0], rest[1], &rest[4..len]));
+        rest = &rest[len..];
+    }
+}
+
+fn int<T: TryFrom<u64>>(text: &str) -> Option<T> {
+    let n = match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => text.parse().ok()?,
+    };
+    n.try_into().ok()
+}
+
+fn encode_node(text: &str) -> Option<(u8, u8, Vec<u8>)> {
+    if text.is_empty() {
+        return None;
+    }
+    let Some((name, args)) = text.strip_suffix(')').and_then(|t| t.split_once('(')) else {
+        // Anything else is a file path.
+        let mut d = text
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+        d.extend([0, 0]);
+        return Some((4, 4, d));
+    };
+    let a = args.split(',').collect::<Vec<_>>();
+    let mut d = Vec::new();
+    let (ty, sub) = match (name, &a[..]) {
+        ("Pci", [dev, func]) => {
+            d.extend([int::<u8>(func)?, int(dev)?]);
+            (1, 1)
+        }
+        ("PciRoot", [uid]) => {
+            d.extend(PNP_PCI_ROOT.to_le_bytes());
+            d.extend(int::<u32>(uid)?.to_le_bytes());
+            (2, 1)
+        }
+        ("Acpi", [hid, uid]) => {
+            d.extend(int::<u32>(hid)?.to_le_bytes());
+            d.extend(int::<u32>(uid)?.to_le_bytes());
+            (2, 1)
+        }
+        ("USB", [port, interface]) => {
+            d.extend([int::<u8>(port)?, int(interface)?]);
+            (3, 5)
+        }
+        ("Sata", [hba, mult, lun]) => {
+            for n in [hba, mult, lun] {
+                d.extend(int::<u16>(n)?.to_le_bytes());
+            }
+            (3, 18)
+        }
+        ("NVMe", [nsid, eui]) => {
+            d.extend(int::<u32>(nsid)?.to_le_bytes());
+            let eui = from_hex(&eui.replace('-', ""))?;
+            if eui.len() != 8 {
+                return None;
+            }
+            d.extend(eui);
+            (3, 23)
+        }
+        ("HD", [part, kind, sig, start, size]) => {
+            d.extend(int::<u32>(part)?.to_le_bytes());
+            d.extend(int::<u64>(start)?.to_le_bytes());
+            d.extend(int::<u64>(size)?.to_le_bytes());
+            match *kind {
+                "GPT" => {
+                    d.extend(parse_guid(sig)?);
+                    d.extend([2, 2]);
+                }
+                "MBR" => {
+                    d.extend(int::<u32>(sig)?.to_le_bytes());
+                    d.extend([0; 12]);
+                    d.extend([1, 1]);
+                }
+                _ => return None,
+            }
+            (4, 1)
+        }
+        ("Path", [ty, sub, hex]) => {
+            d = from_hex(hex)?;
+            (int(ty)?, int(sub)?)
+        }
+        _ => return None,
+    };
+    Some((ty, sub, d))
+}
+
+/// The bytes of the device path in `text`, or `None` if it is not one.
+pub fn encode(text: &str) -> Option<Vec<u8>> {
+    let mut ret = Vec::new();
+    for node in text.split('/') {
+        let (ty, sub, data) = encode_node(node)?;
+        let len = u16::try_from(data.len() + 4).ok()?;
+        ret.extend([ty, sub]);
+        ret.extend(len.to_le_bytes());
+        ret.extend(data);
+    }
+    ret.extend(END);
+    Some(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATH: &str = "PciRoot(0x0)/Pci(0x1c,0x0)/NVMe(0x1,00-11-22-33-44-55-66-77)/\
+        HD(1,GPT,0123ABCD-4567-89EF-0123-456789ABCDEF,0x28,0x64000)/\\EFI\\BOOT\\BOOTAA64.EFI";
+
+    #[test]
+    fn round_trips() {
+        for text in [PATH, "Acpi(0x1,0x2)/USB(0x3,0x0)", "Path(5,6,abcd)"] {
+            assert_eq!(decode(&encode(text).unwrap()).unwrap(), text);
+        }
+    }
+
+    #[test]
+    fn decodes_known_bytes() {
+        let data = [
+            0x01, 0x01, 0x06, 0x00, 0x00, 0x1c, // Pci(0x1c,0x0)
+            0x7f, 0xff, 0x04, 0x00,
+        ];
+        assert_eq!(decode(&data).unwrap(), "Pci(0x1c,0x0)");
+        assert_eq!(encode("Pci(0x1c,0x0)").unwrap(), data);
+    }
+
+    #[test]
+    fn rejects_malformed_paths() {
+        let data = encode(PATH).unwrap();
+        // Without the end node, cut inside a node, and with trailing bytes.
+        assert!(decode(&data[..data.len() - 4]).is_none());
+        assert!(decode(&data[..10]).is_none());
+        assert!(decode(&[&data[..], &[0]].concat()).is_none());
+        assert!(decode(&END).is_none());
+        assert!(encode("Pci(0x1c)").is_none());
+        assert!(encode("HD(1,GPT,not-a-guid,0x28,0x64000)").is_none());
+    }
+}
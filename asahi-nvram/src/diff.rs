@@ -0,0 +1,167 @@
+// SPDX-License-Identifier: MIT
+//! Variable-level comparison of two nvram states.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    io::{self, stdout, IsTerminal, Write},
+};
+
+use apple_nvram::Nvram;
+use serde_json::{json, Value};
+
+use crate::format_value;
+
+/// Unescaped values of the active partition, keyed by (section, name).
+pub type Snapshot = BTreeMap<(&'static str, Vec<u8>), Vec<u8>>;
+
+/// Pending changes by `section:name`: a new value, or `None` to delete.
+pub type Changes = BTreeMap<String, Option<Vec<u8>>>;
+
+pub fn snapshot(nv: &Nvram) -> Snapshot {
+    snapshot_bank(nv, nv.active)
+}
+
+pub fn snapshot_bank(nv: &Nvram, bank: usize) -> Snapshot {
+    let part = &nv.partitions[bank];
+    let mut ret = Snapshot::new();
+    for (section, values) in [
+        ("common", &part.common.values),
+        ("system", &part.system.values),
+    ] {
+        for var in values.values() {
+            ret.insert((section, var.key.to_vec()), var.bytes());
+        }
+    }
+    ret
+}
+
+/// The state after applying `changes`. Names must have a valid section.
+pub fn apply(snapshot: &Snapshot, changes: &Changes) -> Snapshot {
+    let mut ret = snapshot.clone();
+    for (name, value) in changes {
+        let (section, key) = name.split_once(':').unwrap();
+        let section = if section == "common" {
+            "common"
+        } else {
+            "system"
+        };
+        let key = (section, key.as_bytes().to_vec());
+        match value {
+            Some(value) => ret.insert(key, value.clone()),
+            None => ret.remove(&key),
+        };
+    }
+    ret
+}
+
+pub enum Change<'a> {
+    Added(&'a [u8]),
+    Removed(&'a [u8]),
+    Changed(&'a [u8], &'a [u8]),
+}
+
+pub fn changes<'a>(
+    old: &'a Snapshot,
+    new: &'a Snapshot,
+) -> Vec<(&'a (&'static str, Vec<u8>), Change<'a>)> {
+    let mut ret = Vec::new();
+    for (key, old_val) in old {
+        match new.get(key) {
+            None => ret.push((key, Change::Removed(old_val))),
+            Some(new_val) if new_val != old_val => {
+                ret.push((key, Change::Changed(old_val, new_val)))
+            }
+            _ => {}
+        }
+    }
+    for (key, new_val) in new {
+        if !old.contains_key(key) {
+            ret.push((key, Change::Added(new_val)));
+        }
+    }
+    ret.sort_by(|a, b| a.0.cmp(b.0));
+    ret
+}
+
+/// Writes one line per changed variable and returns how many there were.
+pub fn write_to(out: &mut dyn Write, old: &Snapshot, new: &Snapshot) -> io::Result<usize> {
+    let changes = changes(old, new);
+    for ((section, key), change) in &changes {
+        let name = format!("{section}:{}", String::from_utf8_lossy(key));
+        match change {
+            Change::Added(v) => writeln!(out, "+ {name}={}", format_value(v))?,
+            Change::Removed(v) => writeln!(out, "- {name}={}", format_value(v))?,
+            Change::Changed(o, n) => {
+                writeln!(out, "- {name}={}", format_value(o))?;
+                writeln!(out, "+ {name}={}", format_value(n))?;
+            }
+        }
+    }
+    Ok(changes.len())
+}
+
+/// `[{"name": "common:x", "old": ..., "new": ...}]`, with `null` for absent values.
+pub fn to_json(old: &Snapshot, new: &Snapshot) -> Value {
+    let value = |v: &[u8]| Value::String(format_value(v));
+    changes(old, new)
+        .into_iter()
+        .map(|((section, key), change)| {
+            let (old, new) = match change {
+                Change::Added(v) => (Value::Null, value(v)),
+                Change::Removed(v) => (value(v), Value::Null),
+                Change::Changed(o, n) => (value(o), value(n)),
+            };
+            json!({
+                "name": format!("{section}:{}", String::from_utf8_lossy(key)),
+                "old": old,
+                "new": new,
+            })
+        })
+        .collect()
+}
+
+pub fn print(old: &Snapshot, new: &Snapshot) -> usize {
+    write_to(&mut stdout(), old, new).unwrap()
+}
+
+#[cfg(feature = "decode")]
+fn shown(section: &str, key: &[u8], value: &[u8]) -> String {
+    match crate::decode::decode(section, key, value) {
+        Some(d) if !d.text.contains('\n') => format!("{} ({})", d.text, d.tag),
+        _ => format_value(value),
+    }
+}
+
+#[cfg(not(feature = "decode"))]
+fn shown(_section: &str, _key: &[u8], value: &[u8]) -> String {
+    format_value(value)
+}
+
+/// Like [`print`], but decodes values where possible and colors removals red
+/// and additions green when writing to a terminal. For showing what a
+/// command is about to do before it asks for confirmation.
+pub fn preview(old: &Snapshot, new: &Snapshot) -> usize {
+    let color = stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let (red, green, reset) = if color {
+        ("\x1b[31m", "\x1b[32m", "\x1b[0m")
+    } else {
+        ("", "", "")
+    };
+    let changes = changes(old, new);
+    for ((section, key), change) in &changes {
+        let name = format!("{section}:{}", String::from_utf8_lossy(key));
+        let (old, new) = match change {
+            Change::Added(v) => (None, Some(v)),
+            Change::Removed(v) => (Some(v), None),
+            Change::Changed(o, n) => (Some(o), Some(n)),
+        };
+        if let Some(v) = old {
+            println!("{red}- {name}={}{reset}", shown(section, key, v));
+        }
+        if let Some(v) = new {
+            println!("{green}+ {name}={}{reset}", shown(section, key, v));
+        }
+    }
+    changes.len()
+}
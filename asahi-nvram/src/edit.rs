@@ -0,0 +1,126 @@
+// SPDX-License-Identifier: MIT
+//! `edit`: variables go into a temporary file as `section:name=value` lines,
+//! and whatever is left after the editor exits is what gets written. Lines
+//! removed are deletes, lines added are new variables.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    confirm,
+    diff::{Changes, Snapshot},
+    logging, read_var, section_name,
+    staging::escape,
+    Error, Result,
+};
+
+const HEADER: &str = "\
+# Edit, add or remove section:name=value lines, %xx for raw bytes.
+# Lines starting with # are ignored. Leave the file as is to cancel.
+";
+
+// Deleted again on drop, it may well hold secrets.
+struct TempFile(PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+fn editor() -> String {
+    std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_owned())
+}
+
+// Run through the shell so that EDITOR="code --wait" works.
+fn run_editor(path: &Path) -> Result<()> {
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor()))
+        .arg("sh")
+        .arg(path)
+        .status()
+        .map_err(|_| Error::Io)?;
+    if !status.success() {
+        logging::error("editor exited with an error, nothing was changed");
+        return Err(Error::Aborted);
+    }
+    Ok(())
+}
+
+fn parse(text: &str) -> Result<Snapshot> {
+    let mut ret = Snapshot::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fail = |what: &str| {
+            logging::error(&format!("line {}: {what}", i + 1));
+            Error::Parse
+        };
+        let (name, value) = line.split_once('=').ok_or_else(|| fail("no ="))?;
+        let (section, key) = name
+            .split_once(':')
+            .ok_or_else(|| fail("no section: in the name"))?;
+        let section = section_name(section).map_err(|_| fail("unknown section"))?;
+        let value = read_var(value).map_err(|_| fail("bad %xx escape"))?;
+        if ret
+            .insert((section, key.as_bytes().to_vec()), value)
+            .is_some()
+        {
+            return Err(fail("variable given twice"));
+        }
+    }
+    Ok(ret)
+}
+
+/// Lets the user edit `shown`, a subset of `current`, returning what changed.
+pub fn run(current: &Snapshot, shown: &Snapshot) -> Result<Changes> {
+    let mut text = HEADER.to_owned();
+    for ((section, key), value) in shown {
+        text += &format!(
+            "{section}:{}={}\n",
+            String::from_utf8_lossy(key),
+            escape(value)
+        );
+    }
+    let path = std::env::temp_dir().join(format!("asahi-nvram-edit-{}.txt", std::process::id()));
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)
+        .map_err(|_| Error::Io)?;
+    let temp = TempFile(path);
+    file.write_all(text.as_bytes()).map_err(|_| Error::Io)?;
+    drop(file);
+    let edited = loop {
+        run_editor(&temp.0)?;
+        let after = fs::read_to_string(&temp.0).map_err(|_| Error::Io)?;
+        match parse(&after) {
+            Ok(edited) => break edited,
+            Err(_) if confirm("Edit again?") => continue,
+            Err(e) => return Err(e),
+        }
+    };
+    let mut changes = Changes::new();
+    for (section, key) in shown.keys() {
+        if !edited.contains_key(&(*section, key.clone())) {
+            changes.insert(format!("{section}:{}", String::from_utf8_lossy(key)), None);
+        }
+    }
+    for ((section, key), value) in &edited {
+        if current.get(&(*section, key.clone())) != Some(value) {
+            let name = format!("{section}:{}", String::from_utf8_lossy(key));
+            changes.insert(name, Some(value.clone()));
+        }
+    }
+    Ok(changes)
+}
@@ -0,0 +1,62 @@
+// SPDX-License-Identifier: MIT
+//! Commands from the `[hooks]` config table, run around every write.
+//!
+//! Each hook is run through `sh -c` with the pending changes as JSON on stdin.
+//! A failing `pre-write` hook aborts the write, a failing `post-write` hook
+//! only produces a warning.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    sync::OnceLock,
+};
+
+use serde_json::Value;
+
+use crate::{logging, Error, Result};
+
+#[derive(Default)]
+pub struct Hooks {
+    pub pre_write: Vec<String>,
+    pub post_write: Vec<String>,
+}
+
+static HOOKS: OnceLock<Hooks> = OnceLock::new();
+
+pub fn set(hooks: Hooks) {
+    let _ = HOOKS.set(hooks);
+}
+
+fn run(command: &str, input: &[u8]) -> bool {
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::piped())
+        .spawn();
+    let Ok(mut child) = child else {
+        return false;
+    };
+    // A hook is free not to read its input.
+    let _ = child.stdin.take().unwrap().write_all(input);
+    child.wait().is_ok_and(|status| status.success())
+}
+
+pub fn pre_write(changes: &Value) -> Result<()> {
+    let input = changes.to_string();
+    for command in HOOKS.get().map_or(&[][..], |h| &h.pre_write) {
+        if !run(command, input.as_bytes()) {
+            logging::error(&format!("pre-write hook {command:?} failed"));
+            return Err(Error::Aborted);
+        }
+    }
+    Ok(())
+}
+
+pub fn post_write(changes: &Value) {
+    let input = changes.to_string();
+    for command in HOOKS.get().map_or(&[][..], |h| &h.post_write) {
+        if !run(command, input.as_bytes()) {
+            logging::warn(&format!("post-write hook {command:?} failed"));
+        }
+    }
+}
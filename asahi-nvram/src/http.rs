@@ -0,0 +1,120 @@
+// SPDX-License-Identifier: MIT
+//! Read-only HTTP status endpoint for monitoring.
+//!
+//! `GET /status` returns a JSON summary of the store and `GET /metrics`
+//! the same in Prometheus text format. Secret-bearing variables are never
+//! included, whatever the caller asks for.
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    time::Duration,
+};
+
+use apple_nvram::{Nvram, Section};
+use serde_json::{json, Value};
+
+use crate::{
+    decode, device, find_var, format_value, is_secret, logging, metrics::Metrics, BOOT_VARS,
+};
+
+// Requests are served one at a time, so a client that stalls or sends
+// endless headers has to be cut off before it holds up everyone else.
+const TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST: u64 = 16 * 1024;
+
+fn usage(section: &Section) -> Value {
+    json!({
+        "used": section.used_bytes(),
+        "size": section.size_bytes(),
+        "variables": section.values.len(),
+    })
+}
+
+fn status(device: &str) -> Value {
+    let data = match device::read(device) {
+        Ok(d) => d,
+        Err(e) => return json!({ "device": device, "error": format!("{e}") }),
+    };
+    let nv = match Nvram::parse(&data) {
+        Ok(nv) => nv,
+        Err(e) => return json!({ "device": device, "error": format!("{e}") }),
+    };
+    let part = &nv.partitions[nv.active];
+    let mut boot = serde_json::Map::new();
+    for key in BOOT_VARS {
+        if is_secret(key) {
+            continue;
+        }
+        if let Some((section, var)) = find_var(&nv, key) {
+            let raw = var.bytes();
+            let text = match decode::decode(section, var.key, &raw) {
+                Some(d) => d.text,
+                None => format_value(&raw),
+            };
+            boot.insert(format!("{section}:{key}"), json!(text));
+        }
+    }
+    json!({
+        "device": device,
+        "active_bank": nv.active,
+        "generations": nv.partitions.iter().map(|p| p.generation).collect::<Vec<_>>(),
+        "usage": {
+            "common": usage(&part.common),
+            "system": usage(&part.system),
+        },
+        "boot": boot,
+    })
+}
+
+fn respond(mut stream: TcpStream, device: &str, metrics: &mut Metrics) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    let mut request_line = String::new();
+    let mut reader = BufReader::new(stream.try_clone()?.take(MAX_REQUEST));
+    reader.read_line(&mut request_line)?;
+    // Drain the headers, we don't need any of them.
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        line.clear();
+    }
+    let mut parts = request_line.split_whitespace();
+    let json = "application/json";
+    let (code, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/") | Some("/status")) => ("200 OK", json, status(device).to_string()),
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            metrics.render(device),
+        ),
+        (Some("GET"), _) => (
+            "404 Not Found",
+            json,
+            json!({ "error": "not found" }).to_string(),
+        ),
+        _ => (
+            "405 Method Not Allowed",
+            json,
+            json!({ "error": "read-only endpoint" }).to_string(),
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.0 {code}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+pub fn serve(device: &str, addr: &str) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    let mut metrics = Metrics::default();
+    for stream in listener.incoming() {
+        // One bad client shouldn't take the endpoint down.
+        match stream {
+            Ok(stream) => {
+                let _ = respond(stream, device, &mut metrics);
+            }
+            Err(e) => logging::warn(&format!("cannot accept a connection on {addr}: {e}")),
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,64 @@
+// SPDX-License-Identifier: MIT
+//! Batch files for `import`: either a desired-state TOML file (see
+//! [`crate::state`]) when the name ends in `.toml`, or one
+//! `section:name=value` per line with values escaped as `write` takes
+//! them. Blank lines and lines starting with `#` are skipped.
+//!
+//! Every bad line is reported before giving up, so a long provisioning file
+//! can be fixed in one go.
+
+use std::path::Path;
+
+use crate::{config::Config, diff::Changes, expand, logging, read_var, state, Error, Result};
+
+fn parse_line(config: &Config, line: &str) -> std::result::Result<(String, Vec<u8>), String> {
+    let (name, value) = line
+        .split_once('=')
+        .ok_or_else(|| "expected section:name=value".to_owned())?;
+    let name = expand(config, name.trim()).into_owned();
+    match name.split_once(':') {
+        Some(("common" | "system", key)) if !key.is_empty() => {}
+        Some((section, _)) if !section.is_empty() => {
+            return Err(format!("unknown section {section}"))
+        }
+        _ => return Err(format!("{name} has no section")),
+    }
+    let value = read_var(value).map_err(|_| format!("bad value for {name}"))?;
+    Ok((name, value))
+}
+
+pub fn load(config: &Config, path: &str) -> Result<Changes> {
+    let mut changes = Changes::new();
+    if Path::new(path).extension().is_some_and(|e| e == "toml") {
+        for d in state::load(path)? {
+            let name = format!("{}:{}", d.section, d.key);
+            changes.insert(name, Some(d.value));
+        }
+        return Ok(changes);
+    }
+    let text = std::fs::read_to_string(path).map_err(|e| {
+        logging::error(&format!("{path}: {e}"));
+        Error::Io
+    })?;
+    let mut bad = 0;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim_start();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = parse_line(config, line).and_then(|(name, value)| {
+            match changes.insert(name.clone(), Some(value.clone())) {
+                Some(Some(prev)) if prev != value => Err(format!("{name} is given twice")),
+                _ => Ok(()),
+            }
+        });
+        if let Err(e) = parsed {
+            logging::error(&format!("{path}:{}: {e}", n + 1));
+            bad += 1;
+        }
+    }
+    match bad {
+        0 => Ok(changes),
+        _ => Err(Error::Parse),
+    }
+}
@@ -0,0 +1,70 @@
+// SPDX-License-Identifier: MIT
+//! Local notes attached to variables, one TOML table per section. They never
+//! touch the device, so they survive any write.
+//!
+//! ```toml
+//! [common]
+//! boot-args = "required by m1n1"
+//! ```
+
+use std::path::PathBuf;
+
+use toml::{Table, Value};
+
+use crate::{logging, Error, Result};
+
+pub struct Labels {
+    path: Option<PathBuf>,
+    table: Table,
+}
+
+impl Labels {
+    pub fn load(path: Option<PathBuf>) -> Labels {
+        let table = match path.as_ref().map(std::fs::read_to_string) {
+            Some(Ok(text)) => text.parse::<Table>().unwrap_or_else(|e| {
+                logging::warn(&format!(
+                    "ignoring {}: {e}",
+                    path.as_ref().unwrap().display()
+                ));
+                Table::new()
+            }),
+            _ => Table::new(),
+        };
+        Labels { path, table }
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> Option<&str> {
+        self.table.get(section)?.get(key)?.as_str()
+    }
+
+    /// Sets the label, or removes it for `None`.
+    pub fn set(&mut self, section: &str, key: &str, text: Option<&str>) {
+        match text {
+            Some(text) => {
+                let entry = self
+                    .table
+                    .entry(section)
+                    .or_insert_with(|| Value::Table(Table::new()));
+                if let Value::Table(t) = entry {
+                    t.insert(key.to_owned(), Value::String(text.to_owned()));
+                }
+            }
+            None => {
+                if let Some(Value::Table(t)) = self.table.get_mut(section) {
+                    t.remove(key);
+                    if t.is_empty() {
+                        self.table.remove(section);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let path = self.path.as_ref().ok_or(Error::Io)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|_| Error::Io)?;
+        }
+        std::fs::write(path, self.table.to_string()).map_err(|_| Error::Io)
+    }
+}
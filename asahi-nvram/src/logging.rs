@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: MIT
+//! Diagnostics on stderr, either as plain text or as JSON lines.
+//!
+//! Audit events (every write to the device) are only emitted in JSON mode,
+//! where they are meant to be picked up by a log pipeline.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde_json::{json, Value};
+
+static JSON: AtomicBool = AtomicBool::new(false);
+// --quiet: warnings and errors are left to the exit status.
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+pub fn set_json(on: bool) {
+    JSON.store(on, Ordering::Relaxed);
+}
+
+pub fn set_quiet(on: bool) {
+    QUIET.store(on, Ordering::Relaxed);
+}
+
+/// Enables tracing output from the parser and writer: -v for debug, -vv for trace.
+pub fn set_verbosity(level: u8) {
+    let max = match level {
+        0 => return,
+        1 => tracing::Level::DEBUG,
+        _ => tracing::Level::TRACE,
+    };
+    tracing_subscriber::fmt()
+        .with_max_level(max)
+        .with_writer(std::io::stderr)
+        .without_time()
+        .init();
+}
+
+fn emit(level: &str, message: &str, fields: Value) {
+    if QUIET.load(Ordering::Relaxed) && level != "audit" {
+        return;
+    }
+    if JSON.load(Ordering::Relaxed) {
+        let mut record = json!({
+            "timestamp": crate::timestamp(),
+            "level": level,
+            "message": message,
+        });
+        if let (Some(record), Value::Object(fields)) = (record.as_object_mut(), fields) {
+            record.extend(fields);
+        }
+        eprintln!("{record}");
+    } else if level != "audit" {
+        eprintln!("{level}: {message}");
+    }
+}
+
+pub fn warn(message: &str) {
+    emit("warning", message, Value::Null);
+}
+
+pub fn error(message: &str) {
+    emit("error", message, Value::Null);
+}
+
+pub fn audit(message: &str, fields: Value) {
+    emit("audit", message, fields);
+}
@@ -1,13 +1,75 @@
 // SPDX-License-Identifier: MIT
 use std::{
     borrow::Cow,
+    collections::HashMap,
     env,
-    fmt::Debug,
-    fs::OpenOptions,
-    io::{Read, Seek, Write},
+    fmt::{self, Debug},
+    io::{stdin, stdout, IsTerminal, Read, Write},
+    os::unix::io::AsRawFd,
+    path::Path,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, SystemTime},
 };
 
-use apple_nvram::{erase_if_needed, Nvram, Section, UnescapeVal, Variable};
+use apple_nvram::{
+    CHRPHeader, EscapeVal, Format, Layout, Nvram, Partition, Section, UnescapeVal, Variable,
+    BANK_SIZE, NVRAM_SIZE,
+};
+use config::Config;
+use device::Device;
+use labels::Labels;
+use nix::{
+    poll::{poll, PollFd, PollFlags},
+    sys::inotify::Inotify,
+};
+use staging::Staging;
+
+mod backup;
+#[cfg(feature = "decode")]
+mod bplist;
+#[cfg(feature = "completions")]
+mod completions;
+mod config;
+#[cfg(feature = "daemon")]
+mod daemon;
+#[cfg(feature = "dbus")]
+mod dbus;
+#[cfg(feature = "decode")]
+mod decode;
+mod device;
+mod devpath;
+mod diff;
+mod edit;
+mod hooks;
+#[cfg(feature = "daemon")]
+mod http;
+mod import;
+mod labels;
+mod logging;
+#[cfg(feature = "completions")]
+mod man;
+#[cfg(feature = "daemon")]
+mod metrics;
+#[cfg(feature = "daemon")]
+mod mirror;
+#[cfg(feature = "dbus")]
+mod notify;
+mod plist;
+mod record;
+#[cfg(feature = "daemon")]
+mod rpc;
+#[cfg(feature = "script")]
+mod script;
+#[cfg(feature = "shell")]
+mod shell;
+mod space;
+mod staging;
+mod state;
+mod suggest;
+#[cfg(feature = "tui")]
+mod tui;
+mod types;
 
 #[derive(Debug)]
 enum Error {
@@ -18,6 +80,90 @@ enum Error {
     VariableNotFound,
     UnknownPartition,
     InvalidHex,
+    Aborted,
+    NoEncoder,
+    Io,
+    #[cfg(feature = "daemon")]
+    NoService,
+    #[cfg(feature = "daemon")]
+    InvalidMode,
+    #[cfg(all(feature = "daemon", not(feature = "dbus")))]
+    NoDbus,
+    InvalidName,
+    #[cfg(feature = "completions")]
+    UnknownPage,
+    SectionTooFull,
+    Truncated,
+    Conflict,
+    Terminal,
+    Ambiguous,
+    Locked,
+    Verify,
+    Guarded,
+    Secret,
+    #[cfg(feature = "script")]
+    Script,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Error::Parse => "the nvram contents could not be parsed",
+            Error::SectionTooBig => "a section does not fit in its partition",
+            Error::MissingPartitionName => "the variable name needs a partition, e.g. common:",
+            Error::MissingValue => "a value is missing",
+            Error::VariableNotFound => "no such variable",
+            Error::UnknownPartition => "unknown partition, use common or system",
+            Error::InvalidHex => "invalid hex value",
+            Error::Aborted => "aborted",
+            Error::NoEncoder => "this variable cannot be encoded from text",
+            Error::Io => "input/output error",
+            #[cfg(feature = "daemon")]
+            Error::NoService => "nothing to serve, give --dbus, --socket, --http or --mirror",
+            #[cfg(feature = "daemon")]
+            Error::InvalidMode => "invalid socket mode, expected octal",
+            #[cfg(all(feature = "daemon", not(feature = "dbus")))]
+            Error::NoDbus => "this build has no D-Bus support",
+            Error::InvalidName => "invalid variable name",
+            #[cfg(feature = "completions")]
+            Error::UnknownPage => "no such manual page",
+            Error::SectionTooFull => "not enough free space in the section",
+            Error::Truncated => "the nvram image is truncated",
+            Error::Conflict => "conflicting changes",
+            Error::Terminal => "refusing to write to a terminal",
+            Error::Ambiguous => "the name is set in both partitions, give one",
+            Error::Locked => "the device is in use by another process",
+            Error::Verify => "the written data did not read back correctly",
+            Error::Guarded => "refusing to change a guarded variable without --force",
+            Error::Secret => "refusing to print a secret without --show-secrets",
+            #[cfg(feature = "script")]
+            Error::Script => "the script failed",
+        })
+    }
+}
+
+impl Error {
+    /// 2 for bad arguments (like clap's own usage errors), 3 for a missing
+    /// variable, 4 for an unparsable store, 5 for I/O and 1 for the rest.
+    /// `drift` exits with 6, not an error, when the store has drifted.
+    fn exit_code(&self) -> i32 {
+        match self {
+            Error::MissingPartitionName
+            | Error::MissingValue
+            | Error::UnknownPartition
+            | Error::InvalidHex
+            | Error::InvalidName
+            | Error::Ambiguous => 2,
+            #[cfg(feature = "daemon")]
+            Error::NoService | Error::InvalidMode => 2,
+            #[cfg(all(feature = "daemon", not(feature = "dbus")))]
+            Error::NoDbus => 2,
+            Error::VariableNotFound => 3,
+            Error::Parse | Error::SectionTooBig | Error::Truncated => 4,
+            Error::Io | Error::Locked | Error::Verify => 5,
+            _ => 1,
+        }
+    }
 }
 
 impl From<apple_nvram::Error> for Error {
@@ -25,6 +171,11 @@ impl From<apple_nvram::Error> for Error {
         match e {
             apple_nvram::Error::ParseError => Error::Parse,
             apple_nvram::Error::SectionTooBig => Error::SectionTooBig,
+            e @ apple_nvram::Error::UnsupportedVersion(_) => {
+                logging::error(&e.to_string());
+                Error::Parse
+            }
+            apple_nvram::Error::IoError(_) => Error::Io,
         }
     }
 }
@@ -32,103 +183,2266 @@ impl From<apple_nvram::Error> for Error {
 type Result<T> = std::result::Result<T, Error>;
 
 fn main() {
-    real_main().unwrap();
+    if let Err(e) = real_main() {
+        logging::error(&e.to_string());
+        std::process::exit(e.exit_code());
+    }
 }
 
-fn real_main() -> Result<()> {
-    let matches = clap::command!()
-        .arg(clap::arg!(-d --device [DEVICE] "Path to the nvram device."))
+fn cli() -> clap::Command<'static> {
+    let cmd = clap::command!()
+        .arg(clap::arg!(-d --device [DEVICE] "Nvram device, nvmem:<name> or dir:<path>."))
+        .arg(
+            clap::arg!(-f --file [IMAGE] "Work on an image file instead of a device.")
+                .conflicts_with("device"),
+        )
+        .arg(
+            clap::arg!(-v --verbose "Trace parsing and writing, repeat for more detail.")
+                .action(clap::ArgAction::Count),
+        )
+        .arg(clap::arg!(-q --quiet "Print no warnings or errors, only set the exit status."))
+        .arg(clap::arg!(--force "Write past the free-space limit, or change guarded variables."))
+        .arg(clap::arg!(-n --"dry-run" "Show what would be written, but leave the device alone."))
+        .arg(
+            clap::arg!(--"list-keys" "Print partition:name of every variable, for completion.")
+                .hide(true),
+        )
+        .arg(
+            clap::arg!(--"log-format" [FORMAT] "Format of diagnostics on stderr.")
+                .value_parser(["text", "json"])
+                .default_value("text"),
+        )
         .subcommand(
             clap::Command::new("read")
                 .about("Read nvram variables")
+                .arg(clap::arg!(--decode "Decode well-known variables into a readable form."))
+                .arg(clap::arg!(--guid "Show partitions by their GUID, as macOS and EFI do."))
+                .arg(
+                    clap::arg!(--"show-secrets" "Show the values of passwords, keys and the like."),
+                )
+                .arg(
+                    clap::Arg::new("exclude")
+                        .long("exclude")
+                        .value_name("PATTERN")
+                        .help("Skip variables matching a glob (common:fmm-*), may be repeated.")
+                        .takes_value(true)
+                        .multiple_occurrences(true),
+                )
+                .arg(clap::arg!(
+                    -p --partition [PARTITION] "List only this partition, or find bare names in it."
+                ))
+                .arg(
+                    clap::arg!(--"all-banks" "List both banks, including stale values.")
+                        .conflicts_with("variable"),
+                )
+                .arg(bank_arg().conflicts_with("all-banks"))
+                .arg(
+                    clap::arg!(--raw "Print the bare values, without names or escaping.")
+                        .requires("variable")
+                        .conflicts_with("decode"),
+                )
+                .arg(
+                    clap::arg!(--format [FORMAT] "Output format, raw prints only the values.")
+                        .value_parser(["text", "json", "plist", "raw"])
+                        .conflicts_with("decode"),
+                )
+                .arg(
+                    clap::arg!(-o --output [FILE] "Save json, plist or raw output to a file.")
+                        .conflicts_with("decode"),
+                )
+                .arg(
+                    clap::arg!(-H --hex "Show values as a hex dump with offsets.")
+                        .conflicts_with_all(&["decode", "raw", "format", "type"]),
+                )
+                .arg(
+                    clap::arg!(--type [TYPE] "Show values as this type.")
+                        .visible_alias("encoding")
+                        .value_parser(types::TYPES)
+                        .requires("variable")
+                        .conflicts_with_all(&["decode", "raw", "format"]),
+                )
                 .arg(clap::Arg::new("variable").multiple_values(true)),
         )
         .subcommand(
             clap::Command::new("delete")
                 .about("Delete nvram variables")
+                .arg(clap::arg!(-y --yes "Do not ask for confirmation."))
+                .arg(output_arg())
+                .arg(partition_arg())
+                .arg(clap::Arg::new("variable").multiple_values(true)),
+        )
+        .subcommand(
+            clap::Command::new("edit")
+                .about("Edit nvram variables in $EDITOR and write them in one go")
+                .arg(clap::arg!(-y --yes "Do not ask for confirmation."))
                 .arg(clap::Arg::new("variable").multiple_values(true)),
         )
         .subcommand(
             clap::Command::new("write")
                 .about("Write nvram variables")
-                .arg(clap::Arg::new("variable=value").multiple_values(true)),
-        )
-        .get_matches();
-    let default_name = "/dev/mtd0".to_owned();
-    let mut file = OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(matches.get_one::<String>("device").unwrap_or(&default_name))
-        .unwrap();
-    let mut data = Vec::new();
-    file.read_to_end(&mut data).unwrap();
+                .arg(clap::arg!(-y --yes "Do not ask for confirmation."))
+                .arg(output_arg())
+                .arg(partition_arg())
+                .arg(clap::arg!(--encode "Pass values through the matching decoder plugin."))
+                .arg(
+                    clap::arg!(--type [TYPE] "Read values as this type, like 42 for u32.")
+                        .visible_alias("encoding")
+                        .value_parser(types::TYPES)
+                        .conflicts_with("encode"),
+                )
+                .arg(clap::arg!(--plist [FILE] "Write the variables in a macOS nvram -x plist."))
+                .arg(clap::arg!(--"last-wins" "Use the last value if a variable is given twice."))
+                .arg(
+                    clap::Arg::new("variable=value")
+                        .help("Values use %xx for raw bytes, @FILE reads a file and @- stdin.")
+                        .multiple_values(true),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("label")
+                .about("Attach a local note to a variable, shown by read")
+                .arg(clap::arg!(-r --remove "Remove the note."))
+                .arg(clap::arg!(<VARIABLE> "Variable to annotate, e.g. common:boot-args."))
+                .arg(clap::arg!([TEXT] "The note, printed if omitted.").conflicts_with("remove")),
+        )
+        .subcommand(clap::Command::new("info").about("Show the layout and state of both banks"))
+        .subcommand(
+            clap::Command::new("usage")
+                .about("Show how full each section is and what takes up the space")
+                .arg(
+                    clap::arg!(-n --top [COUNT] "How many of the biggest variables to list.")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("5"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("fsck")
+                .about("Check both banks and rewrite a damaged store from what can be salvaged")
+                .arg(clap::arg!(-y --yes "Do not ask for confirmation.")),
+        )
+        .subcommand(
+            clap::Command::new("gc")
+                .about("Rewrite the store with only the current variables, dropping stale entries"),
+        )
+        .subcommand(
+            clap::Command::new("diff")
+                .about("Compare the variables in two images or backups")
+                .arg(clap::arg!(<OLD> "Image or backup to compare from."))
+                .arg(clap::arg!([NEW] "Image or backup to compare to, the device by default.")),
+        )
+        .subcommand(
+            clap::Command::new("history")
+                .about("Show the values a variable has in each bank, oldest first")
+                .arg(partition_arg())
+                .arg(clap::arg!(<VARIABLE> "The variable to look up.")),
+        )
+        .subcommand(
+            clap::Command::new("dump")
+                .about("Save a normalized image of the active variables")
+                .arg(bank_arg())
+                .arg(clap::arg!(-o --output [FILE] "Write the image to a file.")),
+        )
+        .subcommand(
+            clap::Command::new("backup")
+                .about("Save the raw device contents with a checksum")
+                .arg(clap::arg!(<FILE> "Where to save the backup.")),
+        )
+        .subcommand(
+            clap::Command::new("restore")
+                .about("Write a backup back to the device")
+                .arg(clap::arg!(-y --yes "Do not ask for confirmation."))
+                .arg(clap::arg!(<FILE> "Backup made with the backup subcommand.")),
+        )
+        .subcommand(
+            clap::Command::new("import")
+                .about("Write every variable in a file in a single update")
+                .arg(clap::arg!(-y --yes "Do not ask for confirmation."))
+                .arg(clap::arg!(<FILE> "section:name=value lines, or a .toml file like apply's.")),
+        )
+        .subcommand(
+            clap::Command::new("recovery")
+                .about("Set or clear recovery and DFU boot requests")
+                .arg(clap::arg!(-y --yes "Do not ask for confirmation."))
+                .subcommand_required(true)
+                .subcommand(
+                    clap::Command::new("boot-once").about("Boot into recoveryOS on the next boot"),
+                )
+                .subcommand(
+                    clap::Command::new("force-dfu").about("Enter DFU mode on the next boot"),
+                )
+                .subcommand(clap::Command::new("clear").about("Clear pending recovery requests")),
+        )
+        .subcommand(
+            clap::Command::new("copy")
+                .about("Copy a variable, value bytes and all")
+                .arg(clap::arg!(-y --yes "Overwrite the target without asking."))
+                .arg(clap::arg!(<FROM> "Variable to copy."))
+                .arg(clap::arg!(<TO> "New name, in the same partition if it has none.")),
+        )
+        .subcommand(
+            clap::Command::new("rename")
+                .about("Rename a variable, keeping its value")
+                .arg(clap::arg!(-y --yes "Overwrite the target without asking."))
+                .arg(clap::arg!(<FROM> "Variable to rename."))
+                .arg(clap::arg!(<TO> "New name, in the same partition if it has none.")),
+        )
+        .subcommand(
+            clap::Command::new("reset")
+                .about("Delete every variable in common, and in system too with --system")
+                .arg(clap::arg!(-y --yes "Do not ask for confirmation."))
+                .arg(clap::arg!(--system "Also clear system, this needs --force as well.")),
+        )
+        .subcommand(
+            clap::Command::new("boot-args")
+                .about("Edit common:boot-args one argument at a time")
+                .subcommand_required(true)
+                .subcommand(clap::Command::new("get").about("Print the arguments, one per line"))
+                .subcommand(
+                    clap::Command::new("set")
+                        .about("Replace all arguments")
+                        .arg(boot_arg().required(false)),
+                )
+                .subcommand(
+                    clap::Command::new("add")
+                        .about("Add arguments, replacing any with the same name")
+                        .arg(boot_arg()),
+                )
+                .subcommand(
+                    clap::Command::new("remove")
+                        .about("Remove arguments, given as name or name=value")
+                        .arg(boot_arg()),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("boot")
+                .about("Inspect the boot configuration")
+                .subcommand_required(true)
+                .subcommand(
+                    clap::Command::new("summary").about("Print all boot-related variables"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("migrate")
+                .about("Copy user settings from an nvram image or `nvram -p` output")
+                .arg(clap::arg!(-y --yes "Accept all changes without reviewing them."))
+                .arg(clap::arg!(<SOURCE> "Path to the backup to migrate from.")),
+        )
+        .subcommand(
+            clap::Command::new("stage")
+                .about("Queue changes to write later with commit")
+                .arg(clap::arg!(--delete "Queue deleting the variables instead."))
+                .arg(clap::arg!(<VARIABLE> ... "Variables as name=value, or names with --delete.")),
+        )
+        .subcommand(
+            clap::Command::new("unstage")
+                .about("Drop queued changes")
+                .arg(clap::arg!([VARIABLE] ... "Variables to drop, all of them if omitted.")),
+        )
+        .subcommand(clap::Command::new("status").about("Show queued changes"))
+        .subcommand(
+            clap::Command::new("commit")
+                .about("Write all queued changes at once")
+                .arg(clap::arg!(-y --yes "Do not ask for confirmation.")),
+        )
+        .subcommand(
+            clap::Command::new("__complete")
+                // The name completion scripts used for a while.
+                .alias("_complete")
+                .hide(true)
+                .arg(clap::Arg::new("prefix")),
+        )
+        .subcommand(
+            clap::Command::new("watch")
+                .about("Print changes to nvram variables as they happen")
+                .arg(clap::arg!([pattern] "Only watch matching section:name, like system:boot-*"))
+                .arg(
+                    clap::arg!(-i --interval [SECONDS] "How often to re-read the device.")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("2"),
+                )
+                .arg(clap::arg!(--notify "Also show a desktop notification for each change.")),
+        )
+        .subcommand(
+            clap::Command::new("apply")
+                .about("Bring nvram in line with a desired-state file")
+                .arg(
+                    clap::arg!(--converge <FILE> "Write only the variables that differ.")
+                        .default_value("/etc/asahi-nvram/desired.toml"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("drift")
+                .about("Report variables that differ from a desired-state file")
+                .arg(clap::arg!(--manifest <FILE> "Desired-state file to check against.")),
+        )
+        .subcommand(
+            clap::Command::new("record")
+                .about("Append the current nvram state to a change timeline")
+                .arg(clap::arg!(--"state-dir" [DIR] "Where to keep the timeline.")),
+        );
+    #[cfg(feature = "decode")]
+    let cmd = cmd.subcommand(
+        clap::Command::new("paniclog")
+            .alias("panic")
+            .about("Extract the last panic log stored in nvram")
+            .arg(clap::arg!(-o --output [FILE] "Save the panic log to a file.")),
+    );
+    #[cfg(feature = "daemon")]
+    let cmd = cmd.subcommand(
+        clap::Command::new("daemon")
+            .about("Serve nvram access to unprivileged clients")
+            .arg(clap::arg!(--dbus "Provide org.asahilinux.Nvram on the system bus."))
+            .arg(clap::arg!(--socket [PATH] "Serve JSON-RPC on a Unix domain socket."))
+            .arg(
+                clap::arg!(--"socket-mode" [MODE] "Octal file mode of the socket.")
+                    .default_value("600"),
+            )
+            .arg(clap::arg!(--"read-only" "Reject modifications over the socket."))
+            .arg(clap::arg!(--http [ADDR] "Serve status and metrics, e.g. 127.0.0.1:9110."))
+            .arg(clap::arg!(--mirror [DIR] "Mirror variables as files under this directory."))
+            .arg(
+                clap::Arg::new("mirror-var")
+                    .long("mirror-var")
+                    .value_name("VARIABLE")
+                    .help("Variable to mirror, may be repeated.")
+                    .takes_value(true)
+                    .multiple_occurrences(true),
+            )
+            .arg(
+                clap::arg!(-i --interval [SECONDS] "How often to sync mirrored files.")
+                    .value_parser(clap::value_parser!(u64))
+                    .default_value("2"),
+            ),
+    );
+    #[cfg(feature = "completions")]
+    let cmd = cmd
+        .subcommand(
+            clap::Command::new("completions")
+                .about("Print a shell completion script")
+                .arg(
+                    clap::arg!(<SHELL> "Shell to generate completions for.")
+                        .value_parser(clap::value_parser!(clap_complete::Shell)),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("man")
+                .about("Generate man pages")
+                .arg(clap::arg!(-o --"out-dir" [DIR] "Write every page into this directory."))
+                .arg(
+                    clap::arg!([PAGE] "Page to print, e.g. asahi-nvram-read.")
+                        .default_value("asahi-nvram"),
+                ),
+        );
+    #[cfg(feature = "script")]
+    let cmd = cmd.subcommand(
+        clap::Command::new("script")
+            .about("Edit variables with a Rhai script")
+            .arg(clap::arg!(-y --yes "Apply the changes without asking."))
+            .arg(clap::arg!(<FILE> "Script operating on the `nvram` map.")),
+    );
+    #[cfg(feature = "shell")]
+    let cmd = cmd.subcommand(
+        clap::Command::new("shell").about("Stage and commit changes from an interactive prompt"),
+    );
+    #[cfg(feature = "tui")]
+    let cmd =
+        cmd.subcommand(clap::Command::new("tui").about("Browse and edit variables interactively"));
+    cmd
+}
+
+// Arguments like -v are values here, not flags.
+fn boot_arg() -> clap::Arg<'static> {
+    clap::Arg::new("ARG")
+        .required(true)
+        .multiple_values(true)
+        .allow_hyphen_values(true)
+}
+
+fn partition_arg() -> clap::Arg<'static> {
+    clap::arg!(-p --partition [PARTITION] "Partition for names without one, common by default.")
+}
+
+fn bank_arg() -> clap::Arg<'static> {
+    clap::arg!(--bank [BANK] "Use bank 0 or 1, or the inactive one, instead of the active one.")
+        .value_parser(["0", "1", "active", "inactive"])
+}
+
+fn output_arg() -> clap::Arg<'static> {
+    clap::arg!(-o --output [FILE] "Save the new image to a file, or - for stdout, not the device.")
+}
+
+fn bank_index(nv: &Nvram, bank: &str) -> usize {
+    match bank {
+        "active" => nv.active,
+        "inactive" => 1 - nv.active,
+        _ => bank.parse().unwrap(),
+    }
+}
+
+fn real_main() -> Result<()> {
+    let matches = cli().get_matches();
+    logging::set_json(matches.get_one::<String>("log-format").unwrap() == "json");
+    logging::set_verbosity(*matches.get_one::<u8>("verbose").unwrap());
+    logging::set_quiet(matches.contains_id("quiet"));
+    #[cfg(feature = "completions")]
+    if let Some(("completions", args)) = matches.subcommand() {
+        completions::print(*args.get_one("SHELL").unwrap());
+        return Ok(());
+    }
+    #[cfg(feature = "completions")]
+    if let Some(("man", args)) = matches.subcommand() {
+        return match args.get_one::<String>("out-dir") {
+            Some(dir) => man::write_all(Path::new(dir)),
+            None => man::print(args.get_one::<String>("PAGE").unwrap()),
+        };
+    }
+    let mut config = Config::load();
+    hooks::set(std::mem::take(&mut config.hooks));
+    space::set(config.free_space, matches.contains_id("force"));
+    DRY_RUN.store(matches.contains_id("dry-run"), Ordering::Relaxed);
+    FORCE.store(matches.contains_id("force"), Ordering::Relaxed);
+    if let Some(path) = matches.get_one::<String>("file") {
+        if !device::is_image(Path::new(path)) {
+            logging::error(&format!("{path} is not a regular file"));
+            return Err(Error::Io);
+        }
+    }
+    let chosen = matches
+        .get_one::<String>("file")
+        .or(matches.get_one::<String>("device"))
+        .or(config.device.as_ref())
+        .cloned();
+    #[cfg(feature = "dbus")]
+    let explicit = chosen.is_some();
+    let device = chosen.unwrap_or_else(device::default_spec);
+    let device = device.as_str();
+    if matches.contains_id("list-keys") {
+        // Prints nothing rather than failing, e.g. when run unprivileged.
+        let data = device::read(device).unwrap_or_default();
+        if let Ok(nv) = Nvram::parse(&data) {
+            let part = &nv.partitions[nv.active];
+            for (section, values) in [
+                ("common", &part.common.values),
+                ("system", &part.system.values),
+            ] {
+                for key in values.keys() {
+                    println!("{section}:{}", String::from_utf8_lossy(key));
+                }
+            }
+        }
+        return Ok(());
+    }
+    if let Some(("diff", args)) = matches.subcommand() {
+        let old = load_image(args.get_one::<String>("OLD").unwrap())?;
+        let new = match args.get_one::<String>("NEW") {
+            Some(path) => load_image(path)?,
+            None => device::read(device)?,
+        };
+        let (old, new) = (Nvram::parse(&old)?, Nvram::parse(&new)?);
+        if diff::print(&diff::snapshot(&old), &diff::snapshot(&new)) == 0 {
+            println!("no differences");
+        }
+        return Ok(());
+    }
+    if let Some(("label", args)) = matches.subcommand() {
+        let var: &str = &expand(&config, args.get_one::<String>("VARIABLE").unwrap());
+        let (part, name) = var.split_once(':').ok_or(Error::MissingPartitionName)?;
+        let part = section_name(part)?;
+        let mut labels = Labels::load(config.labels_path());
+        match args.get_one::<String>("TEXT") {
+            Some(text) => labels.set(part, name, Some(text)),
+            None if args.contains_id("remove") => labels.set(part, name, None),
+            None => {
+                println!("{}", labels.get(part, name).unwrap_or(""));
+                return Ok(());
+            }
+        }
+        return labels.save();
+    }
+    if let Some(("stage", args)) = matches.subcommand() {
+        let mut staging = Staging::load(config.staging_path())?;
+        for var in args.get_many::<String>("VARIABLE").unwrap() {
+            let (key, value) = if args.contains_id("delete") {
+                (var.as_str(), None)
+            } else {
+                let (key, value) = var.split_once('=').ok_or(Error::MissingValue)?;
+                (key, Some(read_value(value)?))
+            };
+            let key: &str = &expand(&config, key);
+            let (part, _) = key.split_once(':').ok_or(Error::MissingPartitionName)?;
+            section_name(part)?;
+            staging.changes.insert(key.to_owned(), value);
+        }
+        return staging.save();
+    }
+    if let Some(("unstage", args)) = matches.subcommand() {
+        let mut staging = Staging::load(config.staging_path())?;
+        match args.get_many::<String>("VARIABLE") {
+            Some(vars) => {
+                for var in vars {
+                    let var: &str = &expand(&config, var);
+                    if staging.changes.remove(var).is_none() {
+                        logging::warn(&format!("{var} is not staged"));
+                    }
+                }
+            }
+            None => staging.changes.clear(),
+        }
+        return staging.save();
+    }
+    if let Some(("__complete", args)) = matches.subcommand() {
+        // Completion runs unprivileged, so fall back to well-known names only.
+        let data = device::read(device).unwrap_or_default();
+        let prefix = args.get_one::<String>("prefix").map_or("", |s| s.as_str());
+        for name in all_names(Nvram::parse(&data).ok().as_ref()) {
+            if name.starts_with(prefix) {
+                println!("{name}");
+            }
+        }
+        return Ok(());
+    }
+    let write = match matches.subcommand() {
+        Some(("boot-args", args)) => args.subcommand_name() != Some("get"),
+        // With --output the image goes elsewhere, the device is only read.
+        Some((name, args)) => {
+            WRITERS.contains(&name) && !args.try_contains_id("output").unwrap_or(false)
+        }
+        None => false,
+    } && !DRY_RUN.load(Ordering::Relaxed);
+    // Without access to the default device, use the D-Bus service if it is
+    // running. Daemon mode has to open the device itself.
+    #[cfg(feature = "dbus")]
+    let device = match !explicit
+        && matches.subcommand_name() != Some("daemon")
+        && device::denied(device, write)
+        && dbus::Client::connect().is_ok()
+    {
+        true => device::BUS_SPEC,
+        false => device,
+    };
+    let mut file = Device::open(device, write)?;
+    let data = file.read()?;
+    // Flat stores have no banks, and are as big as they are.
+    let flat = Format::detect(&data) == Some(Format::Flat);
+    if data.len() < NVRAM_SIZE && !flat {
+        report_truncated(device, &data);
+        TRUNCATED.store(true, Ordering::Relaxed);
+    }
+    // Before parsing, which is what fails on a store that needs repairing.
+    if let Some(("fsck", args)) = matches.subcommand() {
+        if flat {
+            logging::error("fsck only checks banked stores, this one is flat");
+            return Err(Error::Parse);
+        }
+        return fsck(&mut file, &data, args.contains_id("yes"));
+    }
     let mut nv = Nvram::parse(&data)?;
     match matches.subcommand() {
         Some(("read", args)) => {
+            if let Some(bank) = args.get_one::<String>("bank") {
+                let bank = bank_index(&nv, bank);
+                select_bank(&mut nv, &data, bank)?;
+            }
             let vars = args.get_many::<String>("variable");
+            let decode = args.contains_id("decode") || config.decode;
+            let hide = |var: &Variable| {
+                !args.contains_id("show-secrets") && is_secret(&String::from_utf8_lossy(var.key))
+            };
+            GUID_NAMES.store(args.contains_id("guid"), Ordering::Relaxed);
+            let format = match args.get_one::<String>("format") {
+                _ if args.contains_id("raw") => "raw",
+                Some(format) => format.as_str(),
+                None => "text",
+            };
+            let exclude = args
+                .get_many::<String>("exclude")
+                .unwrap_or_default()
+                .collect::<Vec<_>>();
+            let excluded = |section: &str, key: &[u8]| {
+                let name = format!("{section}:{}", String::from_utf8_lossy(key));
+                exclude.iter().any(|p| glob_match(p, &name))
+            };
+            // (bank if listing all of them, section, variable) in output order
+            let mut found = Vec::new();
+            let list_section = |found: &mut Vec<_>, section: &str| -> Result<()> {
+                let (section, sec) = section_ref(section, &nv)?;
+                for var in sec.values.values() {
+                    if !excluded(section, var.key) {
+                        found.push((None, section, var));
+                    }
+                }
+                Ok(())
+            };
             if let Some(vars) = vars {
+                let partition = args.get_one::<String>("partition");
                 for var in vars {
+                    let var: &str = &expand(&config, var);
+                    let var = &match partition {
+                        _ if var.contains(':') => var.to_owned(),
+                        Some(p) => format!("{p}:{var}"),
+                        None if is_glob(var) => format!("*:{var}"),
+                        None => bare_name(&nv, var)?,
+                    };
+                    if exclude.iter().any(|p| glob_match(p, var)) {
+                        continue;
+                    }
+                    if is_glob(var) {
+                        let matched = glob_vars(&nv, var);
+                        if matched.is_empty() {
+                            logging::error(&format!("nothing matches {var}"));
+                            return Err(Error::VariableNotFound);
+                        }
+                        for (section, v) in matched {
+                            if !excluded(section, v.key) {
+                                found.push((None, section, v));
+                            }
+                        }
+                        continue;
+                    }
                     let (part, name) = var.split_once(':').ok_or(Error::MissingPartitionName)?;
-                    let v = part_by_name(part, &mut nv)?
-                        .values
-                        .get(name.as_bytes())
-                        .ok_or(Error::VariableNotFound)?;
-                    print_var(part, v);
+                    if name.is_empty() {
+                        list_section(&mut found, part)?;
+                        continue;
+                    }
+                    let (section, sec) = section_ref(part, &nv)?;
+                    match sec.values.get(name.as_bytes()) {
+                        Some(v) => found.push((None, section, v)),
+                        None => return Err(suggest::not_found(&nv, var)),
+                    }
+                }
+            } else if args.contains_id("all-banks") {
+                let only = args.get_one::<String>("partition");
+                for (i, bank) in nv.partitions.iter().enumerate() {
+                    for (section, values) in [
+                        ("common", &bank.common.values),
+                        ("system", &bank.system.values),
+                    ] {
+                        if only.is_some_and(|p| p != section) {
+                            continue;
+                        }
+                        for var in values.values() {
+                            if !excluded(section, var.key) {
+                                found.push((Some(i), section, var));
+                            }
+                        }
+                    }
                 }
+            } else if let Some(section) = args.get_one::<String>("partition") {
+                list_section(&mut found, section)?;
             } else {
-                let part = nv.active_part_mut();
-                for var in part.common.values.values() {
-                    print_var("common", var);
+                list_section(&mut found, "common")?;
+                list_section(&mut found, "system")?;
+            }
+            let output = args.get_one::<String>("output");
+            if output.is_some() && format == "text" {
+                logging::error("--output needs --format json, plist or raw");
+                return Err(Error::MissingValue);
+            }
+            // Raw and plist output is data to be fed elsewhere, with no room for
+            // a placeholder, so secrets are refused there instead.
+            if matches!(format, "raw" | "plist") {
+                if let Some((_, section, var)) = found.iter().find(|(_, _, var)| hide(var)) {
+                    logging::error(&format!(
+                        "{}:{} is secret, use --show-secrets or --exclude",
+                        shown(section),
+                        String::from_utf8_lossy(var.key)
+                    ));
+                    return Err(Error::Secret);
+                }
+            }
+            match format {
+                "raw" => {
+                    let mut data = Vec::new();
+                    for (_, _, var) in &found {
+                        data.extend(var.bytes());
+                    }
+                    write_raw(output, &data)?;
                 }
-                for var in part.system.values.values() {
-                    print_var("system", var);
+                "plist" => {
+                    let vars = found
+                        .iter()
+                        .map(|(_, section, var)| {
+                            let raw = var.bytes();
+                            (macos_name(section, var.key), raw)
+                        })
+                        .collect::<Vec<_>>();
+                    let text = plist::to_xml(&vars);
+                    match output {
+                        Some(path) => std::fs::write(path, text).map_err(|_| Error::Io)?,
+                        None => print!("{text}"),
+                    }
+                }
+                "json" => {
+                    let entries = found
+                        .iter()
+                        .map(|(bank, section, var)| {
+                            let raw = var.bytes();
+                            let mut entry = serde_json::json!({
+                                "partition": section,
+                                "key": String::from_utf8_lossy(var.key),
+                                "hex": to_hex(&raw),
+                                "value": format_value(&raw),
+                            });
+                            if hide(var) {
+                                entry["hex"] = serde_json::Value::Null;
+                                entry["value"] = format!("<redacted, {} bytes>", raw.len()).into();
+                            }
+                            if let Some(bank) = bank {
+                                entry["bank"] = (*bank).into();
+                                entry["generation"] = nv.partitions[*bank].generation.into();
+                            }
+                            entry
+                        })
+                        .collect::<Vec<_>>();
+                    let text = serde_json::to_string_pretty(&entries).unwrap() + "\n";
+                    match output {
+                        Some(path) => std::fs::write(path, text).map_err(|_| Error::Io)?,
+                        None => print!("{text}"),
+                    }
+                }
+                _ => {
+                    let labels = Labels::load(config.labels_path());
+                    for (bank, section, var) in &found {
+                        if let Some(text) = labels.get(section, &String::from_utf8_lossy(var.key)) {
+                            println!("# {text}");
+                        }
+                        if let Some(i) = bank {
+                            let active = if *i == nv.active { " active" } else { "" };
+                            let generation = nv.partitions[*i].generation;
+                            print!("[bank {i} gen {generation}{active}] ");
+                        }
+                        match args.get_one::<String>("type") {
+                            _ if hide(var) => println!("{}", redacted(section, var)),
+                            _ if args.contains_id("hex") => print_hex(section, var),
+                            Some(ty) => print_typed(section, var, ty)?,
+                            None => print_var(section, var, decode),
+                        }
+                    }
                 }
             }
         }
         Some(("write", args)) => {
             let vars = args.get_many::<String>("variable=value");
+            let encode = args.contains_id("encode");
+            let yes = args.contains_id("yes");
+            let last_wins = args.contains_id("last-wins");
             nv.prepare_for_write();
+            let mut questions = Vec::new();
+            let mut given = HashMap::new();
+            let partition = args.get_one::<String>("partition");
+            let mut pairs = Vec::new();
+            if let Some(path) = args.get_one::<String>("plist") {
+                let text = std::fs::read_to_string(path).map_err(|_| Error::Io)?;
+                for (name, value) in plist::from_xml(&text)? {
+                    pairs.push((from_macos_name(&name)?, value));
+                }
+            }
             for var in vars.unwrap_or_default() {
                 let (key, value) = var.split_once('=').ok_or(Error::MissingValue)?;
+                let key = qualify(&expand(&config, key), partition);
                 let (part, name) = key.split_once(':').ok_or(Error::MissingPartitionName)?;
+                let value = if encode {
+                    encode_value(part, name, value)?
+                } else if let Some(ty) = args.get_one::<String>("type") {
+                    types::encode(ty, value).ok_or_else(|| {
+                        logging::error(&format!("{value} is not a valid {ty}"));
+                        Error::Parse
+                    })?
+                } else {
+                    read_value(value)?
+                };
+                pairs.push((key, value));
+            }
+            for (key, value) in pairs.iter() {
+                let (part, name) = key.split_once(':').ok_or(Error::MissingPartitionName)?;
+                let value = value.clone();
+                match given.insert(key, value.clone()) {
+                    Some(prev) if prev == value => continue,
+                    Some(_) if last_wins => logging::warn(&format!(
+                        "{key} is given more than once, using the last value"
+                    )),
+                    Some(_) => {
+                        logging::error(&format!("{key} is given conflicting values"));
+                        return Err(Error::Conflict);
+                    }
+                    None if config.is_protected(part, name) => {
+                        questions.push(format!("{key} is protected, really overwrite it?"))
+                    }
+                    None => {}
+                }
                 part_by_name(part, &mut nv)?.values.insert(
                     name.as_bytes(),
-                    Variable {
-                        key: name.as_bytes(),
-                        value: Cow::Owned(read_var(value)?),
-                    },
+                    Variable::from_bytes(name.as_bytes(), &value),
                 );
             }
-            file.rewind().unwrap();
-            let data = nv.serialize()?;
-            erase_if_needed(&file, data.len());
-            file.write_all(&data).unwrap();
+            review(&nv, &questions, yes)?;
+            save_nvram(&mut file, &nv, args.get_one::<String>("output"))?;
         }
         Some(("delete", args)) => {
-            let vars = args.get_many::<String>("variable");
+            let mut vars = Vec::new();
+            let partition = args.get_one::<String>("partition");
+            for var in args.get_many::<String>("variable").unwrap_or_default() {
+                let var = qualify(&expand(&config, var), partition);
+                if !is_glob(&var) {
+                    vars.push(var);
+                    continue;
+                }
+                let matched = glob_vars(&nv, &var);
+                if matched.is_empty() {
+                    logging::warn(&format!("nothing matches {var}"));
+                }
+                for (section, v) in matched {
+                    vars.push(format!("{section}:{}", String::from_utf8_lossy(v.key)));
+                }
+            }
+            let yes = args.contains_id("yes");
             nv.prepare_for_write();
-            for var in vars.unwrap_or_default() {
+            let mut questions = Vec::new();
+            for var in &vars {
                 let (part, name) = var.split_once(':').ok_or(Error::MissingPartitionName)?;
-                part_by_name(part, &mut nv)?.values.remove(name.as_bytes());
+                let values = &mut part_by_name(part, &mut nv)?.values;
+                if !values.contains_key(name.as_bytes()) {
+                    suggest::not_found(&nv, var);
+                    continue;
+                }
+                values.shift_remove(name.as_bytes());
+                if config.is_protected(part, name) {
+                    questions.push(format!("{var} is protected, really delete it?"));
+                }
+                if let Some((_, _, consequence)) = BOOT_CRITICAL_VARS
+                    .iter()
+                    .find(|(s, k, _)| *s == part && *k == name)
+                {
+                    logging::warn(&format!("{var} is boot-critical: {consequence}"));
+                    questions.push(format!("Really delete {var}?"));
+                }
+            }
+            review(&nv, &questions, yes)?;
+            save_nvram(&mut file, &nv, args.get_one::<String>("output"))?;
+        }
+        Some(("edit", args)) => {
+            let current = diff::snapshot(&nv);
+            let mut shown = diff::Snapshot::new();
+            match args.get_many::<String>("variable") {
+                None => shown = current.clone(),
+                Some(vars) => {
+                    for var in vars {
+                        let var: &str = &expand(&config, var);
+                        // Bare names are looked up in both sections, like read does.
+                        let var = match var.contains(':') {
+                            true => var.to_owned(),
+                            false => format!("*:{var}"),
+                        };
+                        let matched = glob_vars(&nv, &var);
+                        if matched.is_empty() {
+                            logging::warn(&format!("nothing matches {var}"));
+                        }
+                        for (section, v) in matched {
+                            let key = (section, v.key.to_vec());
+                            shown.insert(key.clone(), current[&key].clone());
+                        }
+                    }
+                }
+            }
+            let changes = edit::run(&current, &shown)?;
+            if changes.is_empty() {
+                println!("no changes");
+                return Ok(());
+            }
+            let mut questions = Vec::new();
+            for name in changes.keys() {
+                let (part, key) = name.split_once(':').ok_or(Error::MissingPartitionName)?;
+                if config.is_protected(part, key) {
+                    questions.push(format!("{name} is protected, really change it?"));
+                }
+            }
+            questions.push("Apply these changes?".to_owned());
+            nv.prepare_for_write();
+            apply_changes(&mut nv, &changes)?;
+            review(&nv, &questions, args.contains_id("yes"))?;
+            write_nvram(&mut file, &nv)?;
+        }
+        #[cfg(feature = "decode")]
+        Some(("paniclog", args)) => {
+            let part = nv.active_part_mut();
+            let mut chunks = Vec::new();
+            for var in part
+                .common
+                .values
+                .values()
+                .chain(part.system.values.values())
+            {
+                let key = String::from_utf8_lossy(var.key);
+                if key == decode::PANIC_INFO_KEY || key.starts_with(decode::PANIC_INFO_PREFIX) {
+                    chunks.push((key.into_owned(), var));
+                }
+            }
+            if chunks.is_empty() {
+                return Err(Error::VariableNotFound);
+            }
+            chunks.sort_by(|a, b| a.0.cmp(&b.0));
+            let mut data = Vec::new();
+            for (_, var) in chunks {
+                data.extend(var.bytes());
+            }
+            let log = decode::panic_log(&data).ok_or(Error::Parse)?;
+            if let Some(path) = args.get_one::<String>("output") {
+                std::fs::write(path, log).map_err(|e| {
+                    logging::error(&format!("cannot write {path}: {e}"));
+                    Error::Io
+                })?;
+            } else {
+                print!("{log}");
+            }
+        }
+        Some(("info", _args)) if flat => {
+            let part = &nv.partitions[nv.active];
+            println!("flat CHRP store, {:#x} bytes, no banks", data.len());
+            for (name, section) in [("common", &part.common), ("system", &part.system)] {
+                println!(
+                    "  {name}: {} of {} bytes used, {} variables",
+                    section.used_bytes(),
+                    section.size_bytes(),
+                    section.values.len()
+                );
+            }
+        }
+        Some(("usage", args)) => {
+            space::report(&nv, *args.get_one::<usize>("top").unwrap());
+        }
+        Some(("gc", _args)) => {
+            if flat {
+                logging::error("gc only compacts banked stores, this one is flat");
+                return Err(Error::Parse);
+            }
+            for i in 0..2 {
+                if Partition::parse(data.get(i * BANK_SIZE..).unwrap_or_default()).is_err() {
+                    logging::error(&format!("bank {i} does not parse, run fsck first"));
+                    return Err(Error::Parse);
+                }
+            }
+            let part = &nv.partitions[nv.active];
+            let bank = &data[nv.active * BANK_SIZE..];
+            let used = part.common.used_bytes() + part.system.used_bytes();
+            let reclaimed = occupied(bank, part).saturating_sub(used);
+            if reclaimed == 0 {
+                println!("nothing to reclaim");
+                return Ok(());
+            }
+            // The new generation is serialized from the parsed variables
+            // alone, which is all the compacting there is to do.
+            nv.prepare_for_write();
+            write_nvram(&mut file, &nv)?;
+            println!("reclaimed {reclaimed} bytes");
+        }
+        Some(("info", _args)) => {
+            for i in 0..2 {
+                let start = i * BANK_SIZE;
+                let raw = data
+                    .get(start..data.len().min(start + BANK_SIZE))
+                    .unwrap_or_default();
+                // Straight from the bytes, so that a bank which does not
+                // parse still shows why.
+                let Ok(header) = CHRPHeader::parse(raw) else {
+                    println!("bank {i} at {start:#x}: no header");
+                    continue;
+                };
+                let active = if i == nv.active { ", active" } else { "" };
+                println!("bank {i} at {start:#x}{active}");
+                let size = header.size as usize * 16;
+                println!(
+                    "  header: {} signature {:#04x}, {size} bytes",
+                    String::from_utf8_lossy(header.name),
+                    header.signature,
+                );
+                let raw = &raw[..size.min(raw.len())];
+                if raw.len() < 24 {
+                    println!("  too short for a generation and checksum");
+                    continue;
+                }
+                let word = |at: usize| u32::from_le_bytes(raw[at..at + 4].try_into().unwrap());
+                let (adler32, generation) = (word(16), word(20));
+                println!("  generation: {generation}");
+                let computed = Partition::checksum(raw);
+                let verdict = if computed == adler32 {
+                    "ok".to_owned()
+                } else {
+                    format!("mismatch, computed {computed:#010x}")
+                };
+                println!("  adler32: {adler32:#010x} {verdict}");
+                let Ok(bank) = Partition::parse(raw) else {
+                    println!("  variables: invalid");
+                    continue;
+                };
+                for (name, section) in [("common", &bank.common), ("system", &bank.system)] {
+                    let (used, size) = (section.used_bytes(), section.size_bytes());
+                    println!(
+                        "  {name}: {used} of {size} bytes used, {} free, {} variables",
+                        size.saturating_sub(used),
+                        section.values.len()
+                    );
+                }
+            }
+        }
+        Some(("history", args)) => {
+            let var: &str = &expand(&config, args.get_one::<String>("VARIABLE").unwrap());
+            let var = qualify(var, args.get_one::<String>("partition"));
+            let (part, name) = var.split_once(':').unwrap();
+            let section = section_name(part)?;
+            // Each bank on its own: a bank that does not parse has no history
+            // rather than a copy of the other one.
+            let mut banks = (0..2)
+                .filter_map(|i| {
+                    let bank = Partition::parse(data.get(i * BANK_SIZE..)?).ok()?;
+                    Some((i, bank))
+                })
+                .collect::<Vec<_>>();
+            banks.sort_by_key(|(_, bank)| bank.generation);
+            let mut seen = false;
+            let newest = nv.partitions[nv.active].generation;
+            for (i, bank) in &banks {
+                let active = if bank.generation == newest {
+                    " active"
+                } else {
+                    ""
+                };
+                print!("gen {} bank {i}{active}: ", bank.generation);
+                let values = match section {
+                    "common" => &bank.common.values,
+                    _ => &bank.system.values,
+                };
+                match values.get(name.as_bytes()) {
+                    Some(v) => {
+                        seen = true;
+                        print_var(section, v, false);
+                    }
+                    None => println!("{var}: (not set)"),
+                }
+            }
+            if !seen {
+                return Err(Error::VariableNotFound);
+            }
+        }
+        Some(("dump", args)) => {
+            if let Some(bank) = args.get_one::<String>("bank") {
+                let bank = bank_index(&nv, bank);
+                select_bank(&mut nv, &data, bank)?;
+            }
+            // Both banks get the active variables, sorted and at a fixed
+            // generation, so the image depends on nothing but what is set.
+            let mut part = nv.partitions[nv.active].clone();
+            part.generation = 1;
+            part.common.values.sort_keys();
+            part.system.values.sort_keys();
+            let image = Nvram {
+                partitions: [part.clone(), part],
+                active: 1,
+                layout: nv.layout.clone(),
+            }
+            .serialize()?;
+            write_raw(args.get_one::<String>("output"), &image)?;
+        }
+        Some(("backup", args)) => {
+            let path = args.get_one::<String>("FILE").unwrap();
+            std::fs::write(path, backup::create(device, &data)).map_err(|_| Error::Io)?;
+        }
+        Some(("restore", args)) => {
+            let path = args.get_one::<String>("FILE").unwrap();
+            let backup = std::fs::read(path).map_err(|_| Error::Io)?;
+            let image = backup::open(&backup)?;
+            if image.len() < NVRAM_SIZE && Format::detect(image) != Some(Format::Flat) {
+                logging::error(&format!("{path} holds a truncated store, not restoring it"));
+                return Err(Error::Truncated);
+            }
+            let restored = Nvram::parse(image)?;
+            let (before, after) = (diff::snapshot(&nv), diff::snapshot(&restored));
+            diff::preview(&before, &after);
+            if DRY_RUN.load(Ordering::Relaxed) {
+                println!("dry run, nothing written");
+                return Ok(());
+            }
+            if !args.contains_id("yes") && !confirm("Replace the whole store with this backup?") {
+                return Err(Error::Aborted);
+            }
+            let changes = diff::to_json(&before, &after);
+            hooks::pre_write(&changes)?;
+            file.write_image(image)?;
+            if !verify(&mut file, &after)? {
+                logging::error("nvram does not read back as restored, check it before rebooting");
+                return Err(Error::Verify);
+            }
+            logging::audit(
+                "nvram restored",
+                serde_json::json!({ "bytes": image.len(), "from": path }),
+            );
+            hooks::post_write(&changes);
+        }
+        Some(("recovery", args)) => {
+            let (action, _) = args.subcommand().unwrap();
+            let (explanation, set): (&str, &[(&str, &str)]) = match action {
+                "boot-once" => (
+                    "The machine will boot into recoveryOS once instead of the default \
+                     boot volume. This is cleared by the firmware after it is honored.",
+                    &[("recovery-boot-mode", "unused")],
+                ),
+                "force-dfu" => (
+                    "The machine will enter DFU mode on the next boot and will only be \
+                     recoverable from another Mac running Apple Configurator. Only do this \
+                     if you intend to restore or revive the machine.",
+                    &[("force-dfu", "true")],
+                ),
+                _ => (
+                    "Pending recovery and DFU boot requests will be removed.",
+                    &[],
+                ),
+            };
+            println!("{explanation}");
+            if !args.contains_id("yes") && !confirm("Continue?") {
+                return Err(Error::Aborted);
+            }
+            nv.prepare_for_write();
+            let system = &mut nv.active_part_mut().system;
+            for key in RECOVERY_VARS {
+                system.values.shift_remove(key.as_bytes());
+            }
+            for &(key, value) in set {
+                system.values.insert(
+                    key.as_bytes(),
+                    Variable::new(key.as_bytes(), value.as_bytes()),
+                );
+            }
+            write_nvram(&mut file, &nv)?;
+        }
+        Some((action @ ("copy" | "rename"), args)) => {
+            let from: &str = &expand(&config, args.get_one::<String>("FROM").unwrap());
+            let from = match from.contains(':') {
+                true => from.to_owned(),
+                false => bare_name(&nv, from)?,
+            };
+            let (from_part, from_name) = from.split_once(':').unwrap();
+            let from_section = section_name(from_part)?;
+            let to = qualify(
+                &expand(&config, args.get_one::<String>("TO").unwrap()),
+                Some(&from_section.to_owned()),
+            );
+            let (to_part, to_name) = to.split_once(':').unwrap();
+            let to_section = section_name(to_part)?;
+            if from == to {
+                logging::error(&format!("{from} is both the source and the target"));
+                return Err(Error::Conflict);
+            }
+            let (_, sec) = section_ref(from_section, &nv)?;
+            let Some(var) = sec.values.get(from_name.as_bytes()) else {
+                return Err(suggest::not_found(&nv, &from));
+            };
+            let value = var.value.clone().into_owned();
+            let mut questions = Vec::new();
+            if section_ref(to_section, &nv)?
+                .1
+                .values
+                .contains_key(to_name.as_bytes())
+            {
+                questions.push(format!("{to} is already set, overwrite it?"));
+            }
+            for (part, name) in [(to_part, to_name), (from_part, from_name)] {
+                // A copy leaves the source alone.
+                if action == "copy" && name == from_name && part == from_part {
+                    continue;
+                }
+                if config.is_protected(part, name) {
+                    questions.push(format!("{part}:{name} is protected, really change it?"));
+                }
+            }
+            nv.prepare_for_write();
+            if action == "rename" {
+                part_by_name(from_section, &mut nv)?
+                    .values
+                    .shift_remove(from_name.as_bytes());
+            }
+            part_by_name(to_section, &mut nv)?.values.insert(
+                to_name.as_bytes(),
+                Variable {
+                    key: to_name.as_bytes(),
+                    value: Cow::Owned(value),
+                },
+            );
+            review(&nv, &questions, args.contains_id("yes"))?;
+            write_nvram(&mut file, &nv)?;
+        }
+        Some(("reset", args)) => {
+            let system = args.contains_id("system");
+            if system && !FORCE.load(Ordering::Relaxed) {
+                logging::error(
+                    "clearing system loses the boot volume, use --force to do it anyway",
+                );
+                return Err(Error::Guarded);
+            }
+            nv.prepare_for_write();
+            let part = nv.active_part_mut();
+            part.common.values.clear();
+            if system {
+                part.system.values.clear();
+            }
+            let questions = ["Delete all of these variables?".to_owned()];
+            review(&nv, &questions, args.contains_id("yes"))?;
+            // What is guarded in common goes too, that is the point. Clearing
+            // system already took --force.
+            let allowed = GUARDED_VARS
+                .iter()
+                .map(|name| format!("common:{name}"))
+                .collect::<Vec<_>>();
+            write_nvram_allowing(&mut file, &nv, &allowed)?;
+        }
+        Some(("boot-args", args)) => {
+            let (action, args) = args.subcommand().unwrap();
+            let current = find_var(&nv, "boot-args")
+                .map(|(_, v)| v.bytes())
+                .unwrap_or_default();
+            let current = String::from_utf8_lossy(&current).into_owned();
+            let mut tokens = current.split_whitespace().collect::<Vec<_>>();
+            let given = || {
+                args.get_many::<String>("ARG")
+                    .unwrap_or_default()
+                    .map(|s| s.as_str())
+            };
+            // debug=0x14e and debug name the same argument.
+            let name = |t: &str| t.split_once('=').map_or(t, |(n, _)| n).to_owned();
+            match action {
+                "get" => {
+                    for token in tokens {
+                        println!("{token}");
+                    }
+                    return Ok(());
+                }
+                "set" => tokens = given().collect(),
+                "add" => {
+                    for arg in given() {
+                        match tokens.iter().position(|t| name(t) == name(arg)) {
+                            Some(i) => tokens[i] = arg,
+                            None => tokens.push(arg),
+                        }
+                    }
+                }
+                _ => {
+                    for arg in given() {
+                        let before = tokens.len();
+                        tokens.retain(|t| *t != arg && name(t) != arg);
+                        if tokens.len() == before {
+                            logging::warn(&format!("{arg} is not in boot-args"));
+                        }
+                    }
+                }
+            }
+            let value = tokens.join(" ");
+            if value == current.split_whitespace().collect::<Vec<_>>().join(" ") {
+                println!("boot-args unchanged");
+                return Ok(());
+            }
+            let questions = match config.is_protected("common", "boot-args") {
+                true => vec!["common:boot-args is protected, really change it?".to_owned()],
+                false => vec![],
+            };
+            nv.prepare_for_write();
+            let common = &mut nv.active_part_mut().common.values;
+            if value.is_empty() {
+                common.shift_remove(&b"boot-args"[..]);
+            } else {
+                common.insert(b"boot-args", Variable::from_str(b"boot-args", &value));
+            }
+            review(&nv, &questions, false)?;
+            // Editing one argument at a time is the safe way to change it, but
+            // clearing it still needs --force like any other removal.
+            let allowed = match value.is_empty() {
+                true => vec![],
+                false => vec!["common:boot-args".to_owned()],
+            };
+            write_nvram_allowing(&mut file, &nv, &allowed)?;
+        }
+        Some(("boot", _args)) => {
+            let labels = Labels::load(config.labels_path());
+            for key in BOOT_VARS {
+                match find_var(&nv, key) {
+                    Some((section, var)) => {
+                        if let Some(text) = labels.get(section, key) {
+                            println!("# {text}");
+                        }
+                        print_var(section, var, true);
+                    }
+                    None => println!("{key}: (not set)"),
+                }
+            }
+        }
+        Some(("migrate", args)) => {
+            let path = args.get_one::<String>("SOURCE").unwrap();
+            let source = std::fs::read(path).map_err(|e| {
+                logging::error(&format!("{path}: {e}"));
+                Error::Io
+            })?;
+            let candidates = migrate_candidates(&source)?;
+            let yes = args.contains_id("yes");
+            nv.prepare_for_write();
+            let mut changed = false;
+            for (section, key, value) in candidates {
+                // Keep variables in whichever section the firmware already put them.
+                let section = find_var(&nv, key).map_or(section, |(s, _)| s);
+                let values = &mut part_by_name(section, &mut nv)?.values;
+                let old = values.get(key.as_bytes()).map(|v| format_stored(&v.value));
+                let new = format_stored(&value);
+                if old.as_ref() == Some(&new) {
+                    continue;
+                }
+                println!(
+                    "{section}:{key}: {} -> {new}",
+                    old.as_deref().unwrap_or("(not set)")
+                );
+                if yes || confirm("Copy this variable?") {
+                    values.insert(
+                        key.as_bytes(),
+                        Variable {
+                            key: key.as_bytes(),
+                            value: Cow::Owned(value),
+                        },
+                    );
+                    changed = true;
+                }
+            }
+            if changed {
+                write_nvram(&mut file, &nv)?;
+            }
+        }
+        Some(("watch", args)) => {
+            // Every round reads afresh, and holding the lock would keep
+            // writers out.
+            drop(file);
+            let interval = Duration::from_secs(*args.get_one::<u64>("interval").unwrap());
+            let pattern = args
+                .get_one::<String>("pattern")
+                .map(|p| expand(&config, p));
+            let watched = |snapshot: diff::Snapshot| match &pattern {
+                Some(pattern) => snapshot
+                    .into_iter()
+                    .filter(|((section, key), _)| {
+                        glob_match(
+                            pattern,
+                            &format!("{section}:{}", String::from_utf8_lossy(key)),
+                        )
+                    })
+                    .collect(),
+                None => snapshot,
+            };
+            // Image files and directories are re-read as soon as they are
+            // written, devices every interval.
+            let inotify = device::watcher(device);
+            let mut last = watched(diff::snapshot(&nv));
+            #[cfg(feature = "dbus")]
+            let notifier = if args.contains_id("notify") {
+                Some(notify::Notifier::new().map_err(|e| {
+                    logging::error(&format!("cannot reach the session bus: {e}"));
+                    Error::Io
+                })?)
+            } else {
+                None
+            };
+            loop {
+                wait(inotify.as_ref(), interval);
+                let data = device::read(device)?;
+                let current = match Nvram::parse(&data) {
+                    Ok(nv) => watched(diff::snapshot(&nv)),
+                    // Most likely caught halfway through a write, try again later.
+                    Err(_) => continue,
+                };
+                if current != last {
+                    println!("--- {}", timestamp());
+                    diff::print(&last, &current);
+                    #[cfg(feature = "dbus")]
+                    if let Some(notifier) = &notifier {
+                        let names = diff::changes(&last, &current)
+                            .into_iter()
+                            .map(|((section, key), _)| {
+                                format!("{section}:{}", String::from_utf8_lossy(key))
+                            })
+                            .collect::<Vec<_>>();
+                        // Not worth dying over a missing notification daemon.
+                        let _ = notifier.notify("NVRAM variables changed", &names.join("\n"));
+                    }
+                    last = current;
+                }
+            }
+        }
+        #[cfg(feature = "daemon")]
+        Some(("daemon", args)) => {
+            drop(file);
+            // Each service gets a thread, all set up before any of them starts.
+            let mut services: Vec<Box<dyn FnOnce() -> Result<()> + Send>> = Vec::new();
+            if args.contains_id("dbus") {
+                #[cfg(not(feature = "dbus"))]
+                return Err(Error::NoDbus);
+                #[cfg(feature = "dbus")]
+                {
+                    let device = device.to_owned();
+                    services.push(Box::new(move || {
+                        dbus::serve(&device).map_err(|e| {
+                            logging::error(&format!("D-Bus service failed: {e}"));
+                            Error::Io
+                        })
+                    }));
+                }
+            }
+            if let Some(path) = args.get_one::<String>("socket") {
+                let mode = u32::from_str_radix(args.get_one::<String>("socket-mode").unwrap(), 8)
+                    .map_err(|_| Error::InvalidMode)?;
+                let (device, path) = (device.to_owned(), path.clone());
+                let read_only = args.contains_id("read-only");
+                services.push(Box::new(move || {
+                    rpc::serve(&device, &path, mode, read_only).map_err(|e| {
+                        logging::error(&format!("cannot serve on {path}: {e}"));
+                        Error::Io
+                    })
+                }));
+            }
+            if let Some(addr) = args.get_one::<String>("http") {
+                let (device, addr) = (device.to_owned(), addr.clone());
+                services.push(Box::new(move || {
+                    http::serve(&device, &addr).map_err(|e| {
+                        logging::error(&format!("cannot serve on {addr}: {e}"));
+                        Error::Io
+                    })
+                }));
+            }
+            if let Some(dir) = args.get_one::<String>("mirror") {
+                let names = args
+                    .get_many::<String>("mirror-var")
+                    .ok_or(Error::MissingValue)?
+                    .cloned()
+                    .collect::<Vec<_>>();
+                // Names become paths under `dir`, which they must not leave.
+                for name in &names {
+                    let (section, key) = name.split_once(':').ok_or_else(|| {
+                        logging::error(&format!("{name} has no section"));
+                        Error::MissingPartitionName
+                    })?;
+                    section_name(section).inspect_err(|_| {
+                        logging::error(&format!("{name} has an unknown section"));
+                    })?;
+                    if matches!(key, "" | "." | "..") || key.contains('/') {
+                        logging::error(&format!("{name} cannot be mirrored as a file"));
+                        return Err(Error::InvalidName);
+                    }
+                }
+                let interval = Duration::from_secs(*args.get_one::<u64>("interval").unwrap());
+                let (device, dir) = (device.to_owned(), dir.clone());
+                services.push(Box::new(move || {
+                    mirror::serve(&device, &dir, &names, interval)
+                }));
+            }
+            if services.is_empty() {
+                return Err(Error::NoService);
+            }
+            // The daemon lasts as long as all of its services do.
+            let (done, stopped) = std::sync::mpsc::channel();
+            for service in services {
+                let done = done.clone();
+                thread::spawn(move || {
+                    let _ = done.send(service());
+                });
+            }
+            return stopped.recv().unwrap();
+        }
+        Some(("apply", args)) => {
+            let desired = state::load(args.get_one::<String>("converge").unwrap())?;
+            let before = diff::snapshot(&nv);
+            nv.prepare_for_write();
+            for d in &desired {
+                let current = before.get(&(section_name(&d.section)?, d.key.as_bytes().to_vec()));
+                if current == Some(&d.value) {
+                    continue;
+                }
+                part_by_name(&d.section, &mut nv)?.values.insert(
+                    d.key.as_bytes(),
+                    Variable::from_bytes(d.key.as_bytes(), &d.value),
+                );
+            }
+            if diff::print(&before, &diff::snapshot(&nv)) == 0 {
+                println!("already converged");
+                return Ok(());
+            }
+            write_nvram(&mut file, &nv)?;
+        }
+        Some(("drift", args)) => {
+            let desired = state::load(args.get_one::<String>("manifest").unwrap())?;
+            let current = diff::snapshot(&nv);
+            let (mut missing, mut differs) = (0, 0);
+            for d in &desired {
+                let name = format!("{}:{}", d.section, d.key);
+                match current.get(&(section_name(&d.section)?, d.key.as_bytes().to_vec())) {
+                    None => {
+                        missing += 1;
+                        println!("missing {name}");
+                    }
+                    Some(v) if *v != d.value => {
+                        differs += 1;
+                        println!(
+                            "differs {name}: want {}, have {}",
+                            format_value(&d.value),
+                            format_value(v)
+                        );
+                    }
+                    Some(_) => println!("ok      {name}"),
+                }
+            }
+            let ok = desired.len() - missing - differs;
+            println!("{ok} ok, {missing} missing, {differs} differ");
+            // Distinct from every Error::exit_code(), so audits can tell them apart.
+            if missing + differs > 0 {
+                std::process::exit(6);
+            }
+        }
+        Some(("record", args)) => {
+            let dir = args
+                .get_one::<String>("state-dir")
+                .or(config.state_dir.as_ref())
+                .map_or("/var/lib/asahi-nvram", |s| s.as_str());
+            record::record(&nv, Path::new(dir))?;
+        }
+        #[cfg(feature = "script")]
+        Some(("script", args)) => {
+            let before = diff::snapshot(&nv);
+            let after = script::run(Path::new(args.get_one::<String>("FILE").unwrap()), &before)?;
+            if diff::preview(&before, &after) == 0 {
+                println!("no changes");
+                return Ok(());
+            }
+            if !args.contains_id("yes") && !confirm("Apply these changes?") {
+                return Err(Error::Aborted);
+            }
+            nv.prepare_for_write();
+            for (section, key) in before.keys() {
+                if !after.contains_key(&(*section, key.clone())) {
+                    part_by_name(section, &mut nv)?
+                        .values
+                        .shift_remove(&key[..]);
+                }
+            }
+            for ((section, key), value) in &after {
+                if before.get(&(*section, key.clone())) != Some(value) {
+                    part_by_name(section, &mut nv)?
+                        .values
+                        .insert(key, Variable::from_bytes(key, value));
+                }
+            }
+            write_nvram(&mut file, &nv)?;
+        }
+        Some(("status", _args)) => {
+            let staging = Staging::load(config.staging_path())?;
+            let before = diff::snapshot(&nv);
+            if diff::preview(&before, &diff::apply(&before, &staging.changes)) == 0 {
+                println!("nothing staged");
+            }
+        }
+        Some(("import", args)) => {
+            let changes = import::load(&config, args.get_one::<String>("FILE").unwrap())?;
+            let mut questions = Vec::new();
+            for name in changes.keys() {
+                let (part, key) = name.split_once(':').ok_or(Error::MissingPartitionName)?;
+                if config.is_protected(part, key) {
+                    questions.push(format!("{name} is protected, really overwrite it?"));
+                }
+            }
+            nv.prepare_for_write();
+            apply_changes(&mut nv, &changes)?;
+            review(&nv, &questions, args.contains_id("yes"))?;
+            write_nvram(&mut file, &nv)?;
+            println!("imported {} variables", changes.len());
+        }
+        Some(("commit", args)) => {
+            let mut staging = Staging::load(config.staging_path())?;
+            if staging.changes.is_empty() {
+                println!("nothing to commit");
+                return Ok(());
+            }
+            let mut questions = Vec::new();
+            for name in staging.changes.keys() {
+                let (part, key) = name.split_once(':').ok_or(Error::MissingPartitionName)?;
+                if config.is_protected(part, key) {
+                    questions.push(format!("{name} is protected, really change it?"));
+                }
+            }
+            questions.push("Commit these changes?".to_owned());
+            nv.prepare_for_write();
+            apply_changes(&mut nv, &staging.changes)?;
+            review(&nv, &questions, args.contains_id("yes"))?;
+            write_nvram(&mut file, &nv)?;
+            println!("committed {} changes", staging.changes.len());
+            staging.changes.clear();
+            staging.save()?;
+        }
+        #[cfg(feature = "tui")]
+        Some(("tui", _args)) => {
+            let Some(changes) = tui::run(&diff::snapshot(&nv)).map_err(|_| Error::Io)? else {
+                return Ok(());
+            };
+            confirm_protected(&config, &changes)?;
+            nv.prepare_for_write();
+            apply_changes(&mut nv, &changes)?;
+            write_nvram(&mut file, &nv)?;
+            println!("committed {} changes", changes.len());
+        }
+        #[cfg(feature = "shell")]
+        Some(("shell", _args)) => {
+            let mut shell = shell::Shell::new(&config)?;
+            loop {
+                // Start from what is on the device after every commit, in case
+                // someone else wrote in the meantime.
+                let data = file.read()?;
+                let mut nv = Nvram::parse(&data)?;
+                let names = all_names(Some(&nv));
+                let Some(changes) = shell.run(&diff::snapshot(&nv), names)? else {
+                    break;
+                };
+                if let Err(e) = confirm_protected(&config, &changes) {
+                    println!("error: {e}");
+                    continue;
+                }
+                nv.prepare_for_write();
+                apply_changes(&mut nv, &changes)?;
+                match write_nvram(&mut file, &nv) {
+                    Ok(()) => println!("committed {} changes", changes.len()),
+                    Err(e) => println!("error: {e}"),
+                }
             }
-            file.rewind().unwrap();
-            let data = nv.serialize()?;
-            erase_if_needed(&file, data.len());
-            file.write_all(&data).unwrap();
         }
         _ => {}
     }
     Ok(())
 }
 
+// Subcommands that may write to the device; everything else opens it read-only.
+const WRITERS: &[&str] = &[
+    "write", "delete", "edit", "copy", "rename", "fsck", "reset", "restore", "recovery", "migrate",
+    "apply", "script", "commit", "tui", "shell", "import", "gc",
+];
+
+const RECOVERY_VARS: &[&str] = &["recovery-boot-mode", "force-dfu"];
+
+// Variables holding credentials, pairing keys or FileVault unlock data, as
+// globs over the name.
+const SECRET_VARS: &[&str] = &[
+    "preferred-networks",
+    "current-network",
+    "BluetoothUHEDevices",
+    "efi-apple-payload*",
+    "fmm-mobileme-token*",
+];
+
+fn is_secret(key: &str) -> bool {
+    SECRET_VARS.iter().any(|p| glob_match(p, key))
+}
+
+fn redacted(section: &str, var: &Variable) -> String {
+    let len = var.bytes().len();
+    format!(
+        "{}:{}=<redacted, {len} bytes>",
+        shown(section),
+        String::from_utf8_lossy(var.key)
+    )
+}
+
+const BOOT_VARS: &[&str] = &[
+    "boot-volume",
+    "alt-boot-volume",
+    "upgrade-boot-volume",
+    "auto-boot",
+    "boot-args",
+    "recovery-boot-mode",
+    "force-dfu",
+];
+
+// (section, key, what happens without it)
+const BOOT_CRITICAL_VARS: &[(&str, &str, &str)] = &[
+    (
+        "system",
+        "boot-volume",
+        "without a default boot volume the machine boots to the fallback volume or recoveryOS",
+    ),
+    (
+        "system",
+        "auto-boot",
+        "the firmware may stop at the boot picker instead of booting automatically",
+    ),
+];
+
+// Refused without --force in either section, getting one of these wrong can
+// leave the machine unable to boot.
+const GUARDED_VARS: &[&str] = &["boot-args", "boot-volume", "auto-boot"];
+
+const KNOWN_VARS: &[(&str, &str)] = &[
+    ("common", "boot-args"),
+    ("system", "auto-boot"),
+    ("system", "boot-volume"),
+    ("system", "alt-boot-volume"),
+    ("system", "upgrade-boot-volume"),
+    ("system", "recovery-boot-mode"),
+    ("system", "force-dfu"),
+    ("system", "BluetoothUHEDevices"),
+    ("common", "aapl,panic-info"),
+];
+
+const MIGRATE_VARS: &[(&str, &str)] = &[
+    ("system", "SystemAudioVolume"),
+    ("system", "SystemAudioVolumeExtension"),
+    ("system", "SystemAudioVolumeSaved"),
+    ("system", "prev-lang:kbd"),
+    ("system", "prev-lang-diags:kbd"),
+    ("system", "fmm-computer-name"),
+    ("system", "preferred-networks"),
+    ("system", "current-network"),
+];
+
+// Returns (section, key, escaped value) for every migratable variable in the backup.
+fn migrate_candidates(source: &[u8]) -> Result<Vec<(&'static str, &'static str, Vec<u8>)>> {
+    let mut ret = Vec::new();
+    if let Ok(old) = Nvram::parse(source) {
+        for &(_, key) in MIGRATE_VARS {
+            if let Some((section, var)) = find_var(&old, key) {
+                ret.push((section, key, var.value.to_vec()));
+            }
+        }
+        return Ok(ret);
+    }
+    // `nvram -p` prints one `key<TAB>value` line per variable.
+    let text = std::str::from_utf8(source).map_err(|_| Error::Parse)?;
+    for line in text.lines() {
+        let (name, value) = match line.split_once('\t') {
+            Some(kv) => kv,
+            None => continue,
+        };
+        if let Some(&(section, key)) = MIGRATE_VARS.iter().find(|(_, k)| *k == name) {
+            let value = read_var(value)?;
+            ret.push((section, key, EscapeVal::new(value.into_iter()).collect()));
+        }
+    }
+    Ok(ret)
+}
+
+// Like part_by_name(), but without needing the store mutably. Also returns
+// the section name, to use where a `&'static str` is needed.
+fn section_ref<'a, 'b>(name: &str, nv: &'b Nvram<'a>) -> Result<(&'static str, &'b Section<'a>)> {
+    let part = &nv.partitions[nv.active];
+    match name {
+        "common" => Ok(("common", &part.common)),
+        "system" => Ok(("system", &part.system)),
+        _ => {
+            suggest::hint(name, ["common", "system"]);
+            Err(Error::UnknownPartition)
+        }
+    }
+}
+
+// Puts a name without a section into `partition`, common by default.
+fn qualify(name: &str, partition: Option<&String>) -> String {
+    if name.contains(':') {
+        return name.to_owned();
+    }
+    format!("{}:{name}", partition.map_or("common", |p| p.as_str()))
+}
+
+// The full name of a bare `name`, as long as only one section has it.
+fn bare_name(nv: &Nvram, name: &str) -> Result<String> {
+    let part = &nv.partitions[nv.active];
+    let found = [("common", &part.common), ("system", &part.system)]
+        .into_iter()
+        .filter(|(_, sec)| sec.values.contains_key(name.as_bytes()))
+        .map(|(section, _)| section)
+        .collect::<Vec<_>>();
+    match found[..] {
+        [section] => Ok(format!("{section}:{name}")),
+        [] => Err(suggest::not_found(nv, &format!("common:{name}"))),
+        _ => {
+            logging::error(&format!(
+                "{name} is set in both common and system, give the partition"
+            ));
+            Err(Error::Ambiguous)
+        }
+    }
+}
+
+fn is_glob(name: &str) -> bool {
+    name.contains(['*', '?'])
+}
+
+// Active variables whose full `section:name` matches `pattern`.
+fn glob_vars<'a, 'b>(nv: &'b Nvram<'a>, pattern: &str) -> Vec<(&'static str, &'b Variable<'a>)> {
+    let part = &nv.partitions[nv.active];
+    [("common", &part.common), ("system", &part.system)]
+        .into_iter()
+        .flat_map(|(section, sec)| sec.values.values().map(move |v| (section, v)))
+        .filter(|(section, v)| {
+            glob_match(
+                pattern,
+                &format!("{section}:{}", String::from_utf8_lossy(v.key)),
+            )
+        })
+        .collect()
+}
+
+fn find_var<'a, 'b>(nv: &'b Nvram<'a>, key: &str) -> Option<(&'static str, &'b Variable<'a>)> {
+    let part = &nv.partitions[nv.active];
+    if let Some(v) = part.common.values.get(key.as_bytes()) {
+        return Some(("common", v));
+    }
+    part.system
+        .values
+        .get(key.as_bytes())
+        .map(|v| ("system", v))
+}
+
+/// Partition prefixes, names on the device (if it could be read) and
+/// well-known names, sorted.
+fn all_names(nv: Option<&Nvram>) -> Vec<String> {
+    let mut names = vec!["common:".to_owned(), "system:".to_owned()];
+    if let Some(nv) = nv {
+        let part = &nv.partitions[nv.active];
+        for (section, values) in [
+            ("common", &part.common.values),
+            ("system", &part.system.values),
+        ] {
+            for key in values.keys() {
+                names.push(format!("{section}:{}", String::from_utf8_lossy(key)));
+            }
+        }
+    }
+    for (section, key) in KNOWN_VARS.iter().chain(MIGRATE_VARS) {
+        names.push(format!("{section}:{key}"));
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+fn confirm(prompt: &str) -> bool {
+    print!("{prompt} [y/N] ");
+    let mut input = String::new();
+    // A prompt that cannot be shown or answered counts as a no.
+    if stdout()
+        .flush()
+        .and_then(|_| stdin().read_line(&mut input))
+        .is_err()
+    {
+        return false;
+    }
+    matches!(input.trim(), "y" | "Y" | "yes")
+}
+
+// Makes `bank` the active one. Unlike the fallback in Nvram::parse(), a bank
+// that does not parse is an error rather than a copy of the other one.
+fn select_bank<'a>(nv: &mut Nvram<'a>, data: &'a [u8], bank: usize) -> Result<()> {
+    let part = Partition::parse(data.get(bank * BANK_SIZE..).unwrap_or_default())?;
+    nv.partitions[bank] = part;
+    nv.active = bank;
+    Ok(())
+}
+
+// Shows what the pending write changes, then asks each of `questions`
+// unless `yes`. The preview is skipped when nothing would be asked and the
+// output is not a terminal, so scripted use prints nothing extra.
+fn review(nv: &Nvram, questions: &[String], yes: bool) -> Result<()> {
+    // write_nvram() shows the changes instead, and there is nothing to confirm.
+    if DRY_RUN.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let asking = !yes && !questions.is_empty();
+    if asking || stdout().is_terminal() {
+        diff::preview(&diff::snapshot_bank(nv, 1 - nv.active), &diff::snapshot(nv));
+    }
+    if asking && !questions.iter().all(|q| confirm(q)) {
+        return Err(Error::Aborted);
+    }
+    Ok(())
+}
+
+// Set when the device is shorter than a full store. Whatever could be parsed
+// can be read, but writing it back would lose the missing part.
+static TRUNCATED: AtomicBool = AtomicBool::new(false);
+
+// Set by --dry-run: everything up to the write happens, the write does not.
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+// Set by --force, needed to change any of GUARDED_VARS.
+static FORCE: AtomicBool = AtomicBool::new(false);
+
+// Set by read --guid, for print_var() and friends.
+static GUID_NAMES: AtomicBool = AtomicBool::new(false);
+
+// How `section` is printed in front of variable names.
+fn shown(section: &str) -> &str {
+    match GUID_NAMES.load(Ordering::Relaxed) {
+        true => section_guid(section),
+        false => section,
+    }
+}
+
+fn report_truncated(device: &str, data: &[u8]) {
+    let bank = |i: usize| {
+        let start = i * BANK_SIZE;
+        match data.get(start..) {
+            None | Some([]) => "missing",
+            Some(rest) if Partition::parse(rest).is_ok() => "usable",
+            Some(rest) if rest.len() < BANK_SIZE => "incomplete",
+            Some(_) => "invalid",
+        }
+    };
+    logging::warn(&format!(
+        "{device} is truncated ({:#x} of {NVRAM_SIZE:#x} bytes): bank 0 {}, bank 1 {}; \
+         writes are disabled",
+        data.len(),
+        bank(0),
+        bank(1),
+    ));
+}
+
+// write_nvram(), or with `output` the image it would write saved there. The
+// same checks apply, but no hooks run.
+fn save_nvram(file: &mut Device, nv: &Nvram, output: Option<&String>) -> Result<()> {
+    let Some(path) = output else {
+        return write_nvram(file, nv);
+    };
+    if TRUNCATED.load(Ordering::Relaxed) {
+        return Err(Error::Truncated);
+    }
+    check_guarded(
+        &diff::snapshot_bank(nv, 1 - nv.active),
+        &diff::snapshot(nv),
+        &[],
+    )?;
+    space::check(nv)?;
+    if DRY_RUN.load(Ordering::Relaxed) {
+        return dry_run(nv);
+    }
+    let data = nv.serialize()?;
+    write_raw(Some(path).filter(|p| *p != "-"), &data)
+}
+
+// Expects prepare_for_write() to have been called, so the inactive bank holds
+// the previous state.
+fn write_nvram(file: &mut Device, nv: &Nvram) -> Result<()> {
+    write_nvram_allowing(file, nv, &[])
+}
+
+// write_nvram(), letting the `allowed` guarded variables, by full name, change
+// without --force.
+fn write_nvram_allowing(file: &mut Device, nv: &Nvram, allowed: &[String]) -> Result<()> {
+    if TRUNCATED.load(Ordering::Relaxed) {
+        return Err(Error::Truncated);
+    }
+    let (before, after) = (diff::snapshot_bank(nv, 1 - nv.active), diff::snapshot(nv));
+    check_guarded(&before, &after, allowed)?;
+    let changes = diff::to_json(&before, &after);
+    space::check(nv)?;
+    if DRY_RUN.load(Ordering::Relaxed) {
+        return dry_run(nv);
+    }
+    hooks::pre_write(&changes)?;
+    let expected = diff::snapshot(nv);
+    let mut bytes = file.write(nv)?;
+    if !verify(file, &expected)? {
+        logging::warn("nvram does not read back as written, writing it again");
+        bytes = file.write(nv)?;
+        if !verify(file, &expected)? {
+            logging::error("nvram still does not read back as written, check it before rebooting");
+            return Err(Error::Verify);
+        }
+    }
+    tracing::debug!(offset = 0, bytes, "wrote nvram image");
+    let active = &nv.partitions[nv.active];
+    logging::audit(
+        "nvram written",
+        serde_json::json!({
+            "bytes": bytes,
+            "generation": active.generation,
+            "common_variables": active.common.values.len(),
+            "system_variables": active.system.values.len(),
+        }),
+    );
+    hooks::post_write(&changes);
+    Ok(())
+}
+
+// macOS shows common variables by their bare name, and system ones behind
+// the GUID of the system partition.
+const COMMON_GUID: &str = "7C436110-AB2A-4BBB-A880-FE41995C9F82";
+const SYSTEM_GUID: &str = "40A0DDD2-77F8-4392-B4A3-1E7304206516";
+
+fn macos_name(section: &str, key: &[u8]) -> String {
+    let key = String::from_utf8_lossy(key);
+    match section {
+        "system" => format!("{SYSTEM_GUID}:{key}"),
+        _ => key.into_owned(),
+    }
+}
+
+fn guid_section(guid: &str) -> Option<&'static str> {
+    [("common", COMMON_GUID), ("system", SYSTEM_GUID)]
+        .into_iter()
+        .find(|(_, g)| g.eq_ignore_ascii_case(guid))
+        .map(|(section, _)| section)
+}
+
+fn section_guid(section: &str) -> &str {
+    match section {
+        "common" => COMMON_GUID,
+        "system" => SYSTEM_GUID,
+        _ => section,
+    }
+}
+
+// `name` after config aliases, with a partition given by GUID, as macOS and
+// EFI tools write it, turned into common: or system:.
+fn expand<'a>(config: &'a Config, name: &'a str) -> Cow<'a, str> {
+    let name = config.expand(name);
+    match name.split_once(':') {
+        Some((guid, key)) => match guid_section(guid) {
+            Some(section) => Cow::Owned(format!("{section}:{key}")),
+            None => Cow::Borrowed(name),
+        },
+        None => Cow::Borrowed(name),
+    }
+}
+
+fn from_macos_name(name: &str) -> Result<String> {
+    match name.split_once(':') {
+        None => Ok(format!("common:{name}")),
+        Some((guid, key)) if guid_section(guid).is_some() => {
+            Ok(format!("{}:{key}", guid_section(guid).unwrap()))
+        }
+        Some(_) => {
+            logging::error(&format!("{name} is not in a known partition"));
+            Err(Error::UnknownPartition)
+        }
+    }
+}
+
+// The image in a file, which may also be a backup.
+fn load_image(path: &str) -> Result<Vec<u8>> {
+    let data = std::fs::read(path).map_err(|e| {
+        logging::error(&format!("{path}: {e}"));
+        Error::Io
+    })?;
+    match backup::is_backup(&data) {
+        true => Ok(backup::open(&data)?.to_vec()),
+        false => Ok(data),
+    }
+}
+
+// Bytes up to the NUL ending the last entry in each section of `bank`. More
+// than the variables need when stale or duplicate entries are left behind.
+fn occupied(bank: &[u8], part: &Partition) -> usize {
+    let mut at = 32;
+    let mut total = 0;
+    for section in [&part.common, &part.system] {
+        let bytes = bank.get(at..at + section.size_bytes()).unwrap_or_default();
+        total += bytes
+            .iter()
+            .rposition(|&b| b != 0)
+            .map_or(16, |i| (i + 2).max(16));
+        at += section.size_bytes();
+    }
+    total
+}
+
+// Rewrites a clean store from the best bank: an intact one if there is one,
+// otherwise whatever Partition::salvage() recovers, newest generation first.
+fn fsck(file: &mut Device, data: &[u8], yes: bool) -> Result<()> {
+    let mut candidates = Vec::new();
+    for i in 0..2 {
+        let bank = data.get(i * BANK_SIZE..).unwrap_or_default();
+        if let Ok(part) = Partition::parse(bank) {
+            println!("bank {i}: ok, generation {}", part.generation);
+            candidates.push((true, i, part));
+            continue;
+        }
+        match Partition::salvage(bank) {
+            Some(part) => {
+                let count = part.common.values.len() + part.system.values.len();
+                println!(
+                    "bank {i}: damaged, salvaged {count} variables of generation {}",
+                    part.generation
+                );
+                candidates.push((false, i, part));
+            }
+            None => println!("bank {i}: damaged, nothing to salvage"),
+        }
+    }
+    if candidates.len() == 2 && candidates.iter().all(|(intact, _, _)| *intact) {
+        println!("no problems found");
+        return Ok(());
+    }
+    let Some((_, i, part)) = candidates
+        .into_iter()
+        .max_by_key(|(intact, _, part)| (*intact, part.generation))
+    else {
+        logging::error("nothing could be recovered from either bank");
+        return Err(Error::Parse);
+    };
+    let mut nv = Nvram {
+        partitions: [part.clone(), part],
+        active: 0,
+        layout: Layout::Banked,
+    };
+    nv.prepare_for_write();
+    println!("rebuilding the store from bank {i}:");
+    diff::preview(&diff::Snapshot::new(), &diff::snapshot(&nv));
+    if DRY_RUN.load(Ordering::Relaxed) {
+        println!("dry run, nothing written");
+        return Ok(());
+    }
+    if !yes && !confirm("Write the repaired store?") {
+        return Err(Error::Aborted);
+    }
+    write_nvram(file, &nv)
+}
+
+fn check_guarded(
+    before: &diff::Snapshot,
+    after: &diff::Snapshot,
+    allowed: &[String],
+) -> Result<()> {
+    if FORCE.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let guarded = diff::changes(before, after)
+        .into_iter()
+        .filter(|((_, key), _)| GUARDED_VARS.iter().any(|g| g.as_bytes() == *key))
+        .map(|((section, key), _)| format!("{section}:{}", String::from_utf8_lossy(key)))
+        .filter(|name| !allowed.contains(name))
+        .collect::<Vec<_>>();
+    if guarded.is_empty() {
+        return Ok(());
+    }
+    logging::error(&format!(
+        "changing {} can leave the machine unbootable, use --force to do it anyway",
+        guarded.join(", ")
+    ));
+    Err(Error::Guarded)
+}
+
+// Whether the device now holds exactly the `expected` variables.
+fn verify(file: &mut Device, expected: &diff::Snapshot) -> Result<bool> {
+    let data = file.read()?;
+    Ok(Nvram::parse(&data).is_ok_and(|nv| diff::snapshot(&nv) == *expected))
+}
+
+fn dry_run(nv: &Nvram) -> Result<()> {
+    if diff::preview(&diff::snapshot_bank(nv, 1 - nv.active), &diff::snapshot(nv)) == 0 {
+        println!("no changes");
+    }
+    let part = &nv.partitions[nv.active];
+    for (name, section) in [("common", &part.common), ("system", &part.system)] {
+        let (used, size) = (section.used_bytes(), section.size_bytes());
+        println!("{name}: {used} of {size} bytes used");
+    }
+    println!("dry run, nothing written");
+    Ok(())
+}
+
+#[cfg(any(feature = "shell", feature = "tui"))]
+fn confirm_protected(config: &Config, changes: &diff::Changes) -> Result<()> {
+    for name in changes.keys() {
+        let (part, key) = name.split_once(':').ok_or(Error::MissingPartitionName)?;
+        if config.is_protected(part, key)
+            && !confirm(&format!("{name} is protected, really change it?"))
+        {
+            return Err(Error::Aborted);
+        }
+    }
+    Ok(())
+}
+
+// Expects prepare_for_write() to have been called.
+fn apply_changes<'a>(nv: &mut Nvram<'a>, changes: &'a diff::Changes) -> Result<()> {
+    for (name, value) in changes {
+        let (part, key) = name.split_once(':').ok_or(Error::MissingPartitionName)?;
+        let values = &mut part_by_name(part, nv)?.values;
+        match value {
+            Some(value) => {
+                values.insert(key.as_bytes(), Variable::from_bytes(key.as_bytes(), value));
+            }
+            None => {
+                values.shift_remove(key.as_bytes());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn section_name(name: &str) -> Result<&'static str> {
+    match name {
+        "common" => Ok("common"),
+        "system" => Ok("system"),
+        _ => Err(Error::UnknownPartition),
+    }
+}
+
 fn part_by_name<'a, 'b>(name: &str, nv: &'b mut Nvram<'a>) -> Result<&'b mut Section<'a>> {
     match name {
         "common" => Ok(&mut nv.active_part_mut().common),
         "system" => Ok(&mut nv.active_part_mut().system),
-        _ => Err(Error::UnknownPartition),
+        _ => {
+            suggest::hint(name, ["common", "system"]);
+            Err(Error::UnknownPartition)
+        }
+    }
+}
+
+// Shell-style wildcards: `*` matches any run of characters, `?` any one.
+fn glob_match(pattern: &str, name: &str) -> bool {
+    let (p, n) = (pattern.as_bytes(), name.as_bytes());
+    let (mut pi, mut ni) = (0, 0);
+    let mut star = None;
+    while ni < n.len() {
+        if pi < p.len() && (p[pi] == b'?' || p[pi] == n[ni]) {
+            pi += 1;
+            ni += 1;
+        } else if pi < p.len() && p[pi] == b'*' {
+            star = Some((pi, ni));
+            pi += 1;
+        } else if let Some((sp, sn)) = star {
+            pi = sp + 1;
+            ni = sn + 1;
+            star = Some((sp, sn + 1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == b'*')
+}
+
+// Binary output goes to `path` if given, otherwise to stdout as long as that
+// is not a terminal.
+fn write_raw(path: Option<&String>, data: &[u8]) -> Result<()> {
+    match path {
+        Some(path) => std::fs::write(path, data).map_err(|_| Error::Io),
+        None if stdout().is_terminal() => {
+            logging::error("refusing to write binary data to a terminal, use -o or redirect");
+            Err(Error::Terminal)
+        }
+        None => stdout().write_all(data).map_err(|_| Error::Io),
     }
 }
 
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
 fn read_var(val: &str) -> Result<Vec<u8>> {
     let val = val.as_bytes();
     let mut ret = Vec::new();
@@ -136,11 +2450,9 @@ fn read_var(val: &str) -> Result<Vec<u8>> {
     while i < val.len() {
         if val[i] == b'%' {
             ret.push(
-                u8::from_str_radix(
-                    unsafe { std::str::from_utf8_unchecked(&val[i + 1..i + 3]) },
-                    16,
-                )
-                .map_err(|_| Error::InvalidHex)?,
+                val.get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok())
+                    .ok_or(Error::InvalidHex)?,
             );
             i += 2;
         } else {
@@ -151,14 +2463,209 @@ fn read_var(val: &str) -> Result<Vec<u8>> {
     Ok(ret)
 }
 
-fn print_var(section: &str, var: &Variable) {
+// A value given on the command line: `@FILE` for the contents of a file, `@-`
+// for stdin, otherwise read_var() syntax.
+fn read_value(text: &str) -> Result<Vec<u8>> {
+    let Some(path) = text.strip_prefix('@') else {
+        return read_var(text);
+    };
+    let mut data = Vec::new();
+    let read = if path == "-" {
+        stdin().read_to_end(&mut data).map(|_| ())
+    } else {
+        std::fs::read(path).map(|d| data = d)
+    };
+    read.map_err(|e| {
+        logging::error(&format!("{path}: {e}"));
+        Error::Io
+    })?;
+    Ok(data)
+}
+
+#[cfg(feature = "decode")]
+fn encode_value(section: &str, name: &str, text: &str) -> Result<Vec<u8>> {
+    decode::encode(section, name.as_bytes(), text.as_bytes()).ok_or(Error::NoEncoder)
+}
+
+#[cfg(not(feature = "decode"))]
+fn encode_value(_section: &str, _name: &str, _text: &str) -> Result<Vec<u8>> {
+    Err(Error::NoEncoder)
+}
+
+// Prints the decoded form if there is one, returns whether it did.
+#[cfg(feature = "decode")]
+fn print_decoded(section: &str, var: &Variable, raw: &[u8]) -> bool {
+    let Some(d) = decode::decode(section, var.key, raw) else {
+        return false;
+    };
+    let key = String::from_utf8_lossy(var.key);
+    if d.text.contains('\n') {
+        println!("{}:{} ({}):", shown(section), key, d.tag);
+        for line in d.text.lines() {
+            println!("    {line}");
+        }
+    } else {
+        println!("{}:{} ({}): {}", shown(section), key, d.tag, d.text);
+    }
+    true
+}
+
+#[cfg(not(feature = "decode"))]
+fn print_decoded(_section: &str, _var: &Variable, _raw: &[u8]) -> bool {
+    false
+}
+
+fn print_var(section: &str, var: &Variable, decode: bool) {
+    let raw = var.bytes();
+    if decode && print_decoded(section, var, &raw) {
+        return;
+    }
+    println!(
+        "{}:{}={}",
+        shown(section),
+        String::from_utf8_lossy(var.key),
+        format_value(&raw)
+    );
+}
+
+// Like hexdump -C, below a section:name line.
+fn print_hex(section: &str, var: &Variable) {
+    let raw = var.bytes();
+    println!(
+        "{}:{} ({} bytes)",
+        shown(section),
+        String::from_utf8_lossy(var.key),
+        raw.len()
+    );
+    for (i, line) in raw.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for (j, b) in line.iter().enumerate() {
+            hex.push_str(if j == 8 { "  " } else { " " });
+            hex.push_str(&format!("{b:02x}"));
+        }
+        let text = line
+            .iter()
+            .map(|&b| match b {
+                0x20..=0x7e => b as char,
+                _ => '.',
+            })
+            .collect::<String>();
+        println!("{:08x} {hex:<49}  |{text}|", i * 16);
+    }
+}
+
+fn print_typed(section: &str, var: &Variable, ty: &str) -> Result<()> {
+    let key = String::from_utf8_lossy(var.key);
+    let Some(value) = types::decode(ty, var) else {
+        logging::error(&format!("{section}:{key} is not a {ty}"));
+        return Err(Error::Parse);
+    };
+    println!("{}:{key}={value}", shown(section));
+    Ok(())
+}
+
+// Sleeps for `timeout`, or until `inotify` has events if given.
+fn wait(inotify: Option<&Inotify>, timeout: Duration) {
+    let Some(inotify) = inotify else {
+        thread::sleep(timeout);
+        return;
+    };
+    let mut fds = [PollFd::new(inotify.as_raw_fd(), PollFlags::POLLIN)];
+    let ms = timeout.as_millis().try_into().unwrap_or(i32::MAX);
+    if poll(&mut fds, ms).unwrap_or(0) > 0 {
+        // Let the writer finish, then drop the events it caused.
+        thread::sleep(Duration::from_millis(100));
+        let _ = inotify.read_events();
+    }
+}
+
+// UTC, formatted as ISO 8601.
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // Howard Hinnant's civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+fn format_value(raw: &[u8]) -> String {
     let mut value = String::new();
-    for c in UnescapeVal::new(var.value.iter().copied()) {
+    for &c in raw {
         if (c as char).is_ascii() && !(c as char).is_ascii_control() {
             value.push(c as char);
         } else {
             value.push_str(&format!("%{c:02x}"));
         }
     }
-    println!("{}:{}={}", section, String::from_utf8_lossy(var.key), value);
+    value
+}
+
+// Formats a value as stored in a section, i.e. still escaped.
+fn format_stored(stored: &[u8]) -> String {
+    format_value(&UnescapeVal::new(stored.iter().copied()).collect::<Vec<_>>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_matches() {
+        for (pattern, name) in [
+            ("*", ""),
+            ("system:boot-*", "system:boot-volume"),
+            ("*:boot-args", "common:boot-args"),
+            ("sys?em:*-volume", "system:alt-boot-volume"),
+            ("*a*b*", "xaxxbx"),
+            ("exact", "exact"),
+        ] {
+            assert!(glob_match(pattern, name), "{pattern} {name}");
+        }
+    }
+
+    #[test]
+    fn guarded_changes_need_to_be_allowed() {
+        let var = |section, key: &str| ((section, key.as_bytes().to_vec()), b"x".to_vec());
+        let before =
+            diff::Snapshot::from([var("common", "boot-args"), var("system", "boot-volume")]);
+        let after = diff::Snapshot::new();
+        let allowed = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert!(check_guarded(&before, &before, &[]).is_ok());
+        assert!(check_guarded(&before, &after, &allowed(&["common:boot-args"])).is_err());
+        assert!(check_guarded(
+            &before,
+            &after,
+            &allowed(&["common:boot-args", "system:boot-volume"])
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn glob_mismatches() {
+        for (pattern, name) in [
+            ("", "x"),
+            ("system:boot-*", "common:boot-args"),
+            ("?", ""),
+            ("*a*b", "xaxxbx"),
+            ("exact", "exactly"),
+        ] {
+            assert!(!glob_match(pattern, name), "{pattern} {name}");
+        }
+    }
 }
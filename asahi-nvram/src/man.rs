@@ -0,0 +1,48 @@
+// SPDX-License-Identifier: MIT
+//! Man pages generated from the clap definitions.
+
+use std::{fs::File, io::stdout, path::Path};
+
+use clap::Command;
+
+use crate::{Error, Result};
+
+/// Prints the page for `name` (e.g. `asahi-nvram-read`) to stdout.
+pub fn print(name: &str) -> Result<()> {
+    let page = pages(crate::cli())
+        .into_iter()
+        .find(|page| page.get_name() == name)
+        .ok_or(Error::UnknownPage)?;
+    clap_mangen::Man::new(page)
+        .render(&mut stdout())
+        .map_err(|_| Error::Io)
+}
+
+/// Writes `<name>.1` into `dir` for the main command and every subcommand.
+pub fn write_all(dir: &Path) -> Result<()> {
+    for page in pages(crate::cli()) {
+        let path = dir.join(format!("{}.1", page.get_name()));
+        let mut file = File::create(path).map_err(|_| Error::Io)?;
+        clap_mangen::Man::new(page)
+            .render(&mut file)
+            .map_err(|_| Error::Io)?;
+    }
+    Ok(())
+}
+
+// Subcommand pages are named like `git-commit`, nested ones get one more level.
+fn pages(mut cmd: Command<'static>) -> Vec<Command<'static>> {
+    cmd.build();
+    let mut ret = Vec::new();
+    collect(&cmd, cmd.get_name().to_owned(), &mut ret);
+    ret
+}
+
+fn collect(cmd: &Command<'static>, name: String, out: &mut Vec<Command<'static>>) {
+    for sub in cmd.get_subcommands() {
+        if !sub.is_hide_set() && sub.get_name() != "help" {
+            collect(sub, format!("{name}-{}", sub.get_name()), out);
+        }
+    }
+    out.push(cmd.clone().name(name));
+}
@@ -0,0 +1,121 @@
+// SPDX-License-Identifier: MIT
+//! Prometheus exposition of store health and usage.
+
+use std::fmt::Write;
+
+use apple_nvram::{Nvram, Partition, BANK_SIZE};
+
+use crate::device;
+
+#[derive(Default)]
+pub struct Metrics {
+    // Generation of the active bank when we first looked, to count writes.
+    first_generation: Option<u32>,
+}
+
+// `samples` are (labels, value) pairs, labels already formatted as `k="v"`.
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, u64)]) {
+    writeln!(out, "# HELP asahi_nvram_{name} {help}").unwrap();
+    writeln!(out, "# TYPE asahi_nvram_{name} {kind}").unwrap();
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            writeln!(out, "asahi_nvram_{name} {value}").unwrap();
+        } else {
+            writeln!(out, "asahi_nvram_{name}{{{labels}}} {value}").unwrap();
+        }
+    }
+}
+
+impl Metrics {
+    pub fn render(&mut self, device: &str) -> String {
+        let mut out = String::new();
+        let data = device::read(device).unwrap_or_default();
+        let nv = Nvram::parse(&data);
+        metric(
+            &mut out,
+            "up",
+            "gauge",
+            "Whether the store could be parsed.",
+            &[(String::new(), nv.is_ok() as u64)],
+        );
+        let valid = (0..2)
+            .map(|bank| {
+                let ok = data.len() >= (bank + 1) * BANK_SIZE
+                    && Partition::parse(&data[bank * BANK_SIZE..]).is_ok();
+                (format!("bank=\"{bank}\""), ok as u64)
+            })
+            .collect::<Vec<_>>();
+        metric(
+            &mut out,
+            "bank_valid",
+            "gauge",
+            "Whether the bank's checksums are valid.",
+            &valid,
+        );
+        let nv = match nv {
+            Ok(nv) => nv,
+            Err(_) => return out,
+        };
+
+        let generations = nv
+            .partitions
+            .iter()
+            .enumerate()
+            .map(|(bank, p)| (format!("bank=\"{bank}\""), p.generation as u64))
+            .collect::<Vec<_>>();
+        metric(
+            &mut out,
+            "bank_generation",
+            "gauge",
+            "Generation counter of each bank.",
+            &generations,
+        );
+        metric(
+            &mut out,
+            "active_bank",
+            "gauge",
+            "Index of the bank in use.",
+            &[(String::new(), nv.active as u64)],
+        );
+
+        let part = &nv.partitions[nv.active];
+        let sections = [("common", &part.common), ("system", &part.system)];
+        let per_section = |f: &dyn Fn(&apple_nvram::Section) -> usize| {
+            sections
+                .iter()
+                .map(|(name, s)| (format!("section=\"{name}\""), f(s) as u64))
+                .collect::<Vec<_>>()
+        };
+        metric(
+            &mut out,
+            "section_used_bytes",
+            "gauge",
+            "Bytes used in a section.",
+            &per_section(&|s| s.used_bytes()),
+        );
+        metric(
+            &mut out,
+            "section_size_bytes",
+            "gauge",
+            "Capacity of a section.",
+            &per_section(&|s| s.size_bytes()),
+        );
+        metric(
+            &mut out,
+            "section_variables",
+            "gauge",
+            "Variables in a section.",
+            &per_section(&|s| s.values.len()),
+        );
+
+        let first = *self.first_generation.get_or_insert(part.generation);
+        metric(
+            &mut out,
+            "writes_total",
+            "counter",
+            "Writes observed since the exporter started.",
+            &[(String::new(), part.generation.wrapping_sub(first) as u64)],
+        );
+        out
+    }
+}
@@ -0,0 +1,89 @@
+// SPDX-License-Identifier: MIT
+//! Mirrors chosen variables as files, efivarfs style.
+//!
+//! Each variable lives at `<dir>/<section>/<name>` holding the raw value.
+//! Edits made to the files are collected every interval and written back
+//! in one go; changes made to the nvram by others are copied to the files.
+
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    thread,
+    time::Duration,
+};
+
+use crate::{daemon::Store, logging, Error, Result};
+
+fn path_for(dir: &Path, name: &str) -> Result<PathBuf> {
+    let (section, key) = name.split_once(':').ok_or(Error::MissingPartitionName)?;
+    if key.contains('/') {
+        return Err(Error::InvalidName);
+    }
+    Ok(dir.join(section).join(key))
+}
+
+fn read_file(path: &Path) -> Result<Option<Vec<u8>>> {
+    match fs::read(path) {
+        Ok(v) => Ok(Some(v)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(_) => Err(Error::Io),
+    }
+}
+
+fn current(store: &Store, names: &[String]) -> Result<HashMap<String, Option<Vec<u8>>>> {
+    let snap = store.snapshot()?;
+    Ok(names
+        .iter()
+        .map(|name| {
+            // The caller of serve() has checked every name.
+            let (section, key) = name.split_once(':').unwrap();
+            let value = snap
+                .iter()
+                .find(|((s, k), _)| *s == section && k == key.as_bytes())
+                .map(|(_, v)| v.clone());
+            (name.clone(), value)
+        })
+        .collect())
+}
+
+pub fn serve(device: &str, dir: &str, names: &[String], interval: Duration) -> Result<()> {
+    let dir = Path::new(dir);
+    for section in ["common", "system"] {
+        fs::create_dir_all(dir.join(section)).map_err(|_| Error::Io)?;
+    }
+    let store = Store::new(device);
+    let mut known = HashMap::new();
+    loop {
+        let mut values = current(&store, names)?;
+        let mut edits = Vec::new();
+        for name in names {
+            let file = read_file(&path_for(dir, name)?)?;
+            if known.contains_key(name) && known.get(name) != Some(&file) {
+                edits.push((name.as_str(), file));
+            }
+        }
+        if !edits.is_empty() {
+            let batch = edits
+                .iter()
+                .map(|(name, value)| (*name, value.as_deref()))
+                .collect::<Vec<_>>();
+            match store.apply(&batch) {
+                Ok(()) => values = current(&store, names)?,
+                Err(e) => logging::error(&format!("failed to write back changes: {e}")),
+            }
+        }
+        for (name, value) in &values {
+            let path = path_for(dir, name)?;
+            if read_file(&path)? == *value {
+                continue;
+            }
+            match value {
+                Some(v) => fs::write(&path, v).map_err(|_| Error::Io)?,
+                None => fs::remove_file(&path).map_err(|_| Error::Io)?,
+            }
+        }
+        known = values;
+        thread::sleep(interval);
+    }
+}
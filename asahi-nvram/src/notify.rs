@@ -0,0 +1,39 @@
+// SPDX-License-Identifier: MIT
+//! Desktop notifications through org.freedesktop.Notifications.
+
+use std::collections::HashMap;
+
+use zbus::{blocking::Connection, zvariant::Value};
+
+pub struct Notifier {
+    conn: Connection,
+}
+
+impl Notifier {
+    pub fn new() -> zbus::Result<Notifier> {
+        Ok(Notifier {
+            conn: Connection::session()?,
+        })
+    }
+
+    pub fn notify(&self, summary: &str, body: &str) -> zbus::Result<()> {
+        let hints = HashMap::<&str, Value>::new();
+        self.conn.call_method(
+            Some("org.freedesktop.Notifications"),
+            "/org/freedesktop/Notifications",
+            Some("org.freedesktop.Notifications"),
+            "Notify",
+            &(
+                "asahi-nvram",
+                0u32,
+                "dialog-information",
+                summary,
+                body,
+                Vec::<&str>::new(),
+                hints,
+                -1i32,
+            ),
+        )?;
+        Ok(())
+    }
+}
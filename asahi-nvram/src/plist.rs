@@ -0,0 +1,185 @@
+// SPDX-License-Identifier: MIT
+//! XML property lists as written and read by macOS `nvram -x`: a single
+//! dict with a `<string>` for printable values and `<data>` for the rest.
+
+use crate::{logging, Error, Result};
+
+const HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+"#;
+const FOOTER: &str = "</dict>\n</plist>\n";
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64(data: &[u8]) -> String {
+    let mut ret = String::new();
+    for chunk in data.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | ((b as u32) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                ret.push(BASE64[((n >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                ret.push('=');
+            }
+        }
+    }
+    ret
+}
+
+fn unbase64(text: &str) -> Option<Vec<u8>> {
+    let mut ret = Vec::new();
+    let (mut n, mut bits) = (0u32, 0);
+    for c in text
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace() && *c != b'=')
+    {
+        let v = BASE64.iter().position(|&b| b == c)? as u32;
+        n = (n << 6) | v;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            ret.push((n >> bits) as u8);
+        }
+    }
+    Some(ret)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn unescape(text: &str) -> Option<String> {
+    let mut ret = String::new();
+    let mut rest = text;
+    while let Some(amp) = rest.find('&') {
+        ret.push_str(&rest[..amp]);
+        let semi = rest[amp..].find(';')? + amp;
+        let c = match &rest[amp + 1..semi] {
+            "amp" => '&',
+            "lt" => '<',
+            "gt" => '>',
+            "quot" => '"',
+            "apos" => '\'',
+            code => {
+                let n = match code.strip_prefix("#x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => code.strip_prefix('#')?.parse().ok()?,
+                };
+                char::from_u32(n)?
+            }
+        };
+        ret.push(c);
+        rest = &rest[semi + 1..];
+    }
+    ret.push_str(rest);
+    Some(ret)
+}
+
+// Like format_value(): plain text gets a <string>, anything else <data>.
+fn printable(value: &[u8]) -> Option<&str> {
+    let text = std::str::from_utf8(value).ok()?;
+    (!text
+        .chars()
+        .any(|c| c.is_control() && c != '\n' && c != '\t'))
+    .then_some(text)
+}
+
+pub fn to_xml(vars: &[(String, Vec<u8>)]) -> String {
+    let mut ret = HEADER.to_owned();
+    for (name, value) in vars {
+        ret += &format!("\t<key>{}</key>\n", escape(name));
+        match printable(value) {
+            Some(text) => ret += &format!("\t<string>{}</string>\n", escape(text)),
+            None => ret += &format!("\t<data>\n\t{}\n\t</data>\n", base64(value)),
+        }
+    }
+    ret + FOOTER
+}
+
+// The text of the element `tag` at the start of `text`, and what follows it.
+fn element<'a>(text: &'a str, tag: &str) -> Option<(&'a str, &'a str)> {
+    let text = text.trim_start();
+    if let Some(rest) = text.strip_prefix(&format!("<{tag}/>")) {
+        return Some(("", rest));
+    }
+    let rest = text.strip_prefix(&format!("<{tag}>"))?;
+    let end = rest.find(&format!("</{tag}>"))?;
+    Some((&rest[..end], &rest[end + tag.len() + 3..]))
+}
+
+pub fn from_xml(text: &str) -> Result<Vec<(String, Vec<u8>)>> {
+    let fail = |what: &str| {
+        logging::error(&format!("bad plist: {what}"));
+        Error::Parse
+    };
+    let start = text.find("<dict>").ok_or_else(|| fail("no <dict>"))?;
+    let mut rest = &text[start + "<dict>".len()..];
+    let mut ret = Vec::new();
+    loop {
+        if rest.trim_start().starts_with("</dict>") {
+            return Ok(ret);
+        }
+        let (key, after) = element(rest, "key").ok_or_else(|| fail("expected <key>"))?;
+        let key = unescape(key).ok_or_else(|| fail("bad entity in a key"))?;
+        let (value, after) = if let Some((v, after)) = element(after, "string") {
+            let v = unescape(v).ok_or_else(|| fail("bad entity in a string"))?;
+            (v.into_bytes(), after)
+        } else if let Some((v, after)) = element(after, "data") {
+            (unbase64(v).ok_or_else(|| fail("bad base64"))?, after)
+        } else {
+            return Err(fail(&format!("{key} is not a string or data")));
+        };
+        ret.push((key, value));
+        rest = after;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // From RFC 4648.
+    const VECTORS: &[(&str, &str)] = &[
+        ("", ""),
+        ("f", "Zg=="),
+        ("fo", "Zm8="),
+        ("foo", "Zm9v"),
+        ("foob", "Zm9vYg=="),
+        ("fooba", "Zm9vYmE="),
+        ("foobar", "Zm9vYmFy"),
+    ];
+
+    #[test]
+    fn base64_vectors() {
+        for (plain, encoded) in VECTORS {
+            assert_eq!(base64(plain.as_bytes()), *encoded);
+            assert_eq!(unbase64(encoded).unwrap(), plain.as_bytes());
+        }
+    }
+
+    #[test]
+    fn unbase64_skips_whitespace() {
+        assert_eq!(unbase64("\n\tZm9v\n\tYmFy\n").unwrap(), b"foobar");
+        assert!(unbase64("Zm9v!").is_none());
+    }
+
+    #[test]
+    fn xml_round_trips() {
+        let vars = vec![
+            ("boot-args".to_owned(), b"debug=0x14e <&>".to_vec()),
+            ("auto-boot".to_owned(), b"true".to_vec()),
+            ("blob".to_owned(), vec![0, 1, 2, 0xff]),
+            ("empty".to_owned(), vec![]),
+        ];
+        let xml = to_xml(&vars);
+        assert!(xml.contains("<data>\n\tAAEC/w==\n\t</data>"));
+        assert_eq!(from_xml(&xml).unwrap(), vars);
+    }
+}
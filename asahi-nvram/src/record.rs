@@ -0,0 +1,66 @@
+// SPDX-License-Identifier: MIT
+//! Boot-to-boot change timeline.
+//!
+//! `<dir>/last` holds the previous state as `section:key=<hex>` lines, and
+//! every run appends a digest of the current state plus the diff against
+//! `last` to `<dir>/timeline`.
+
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use apple_nvram::Nvram;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    diff::{self, Snapshot},
+    from_hex, section_name, timestamp, to_hex, Error, Result,
+};
+
+fn serialize(snap: &Snapshot) -> String {
+    let mut out = String::new();
+    for ((section, key), value) in snap {
+        out.push_str(&format!(
+            "{section}:{}={}\n",
+            String::from_utf8_lossy(key),
+            to_hex(value)
+        ));
+    }
+    out
+}
+
+fn deserialize(text: &str) -> Result<Snapshot> {
+    let mut snap = Snapshot::new();
+    for line in text.lines() {
+        let (name, value) = line.rsplit_once('=').ok_or(Error::Parse)?;
+        let (section, key) = name.split_once(':').ok_or(Error::Parse)?;
+        snap.insert(
+            (section_name(section)?, key.as_bytes().to_vec()),
+            from_hex(value).ok_or(Error::InvalidHex)?,
+        );
+    }
+    Ok(snap)
+}
+
+pub fn record(nv: &Nvram, dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).map_err(|_| Error::Io)?;
+    let current = diff::snapshot(nv);
+    let text = serialize(&current);
+    let digest = to_hex(&Sha256::digest(text.as_bytes()));
+    let last = match fs::read_to_string(dir.join("last")) {
+        Ok(t) => deserialize(&t)?,
+        Err(_) => Snapshot::new(),
+    };
+    let mut timeline = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join("timeline"))
+        .map_err(|_| Error::Io)?;
+    let mut entry = Vec::new();
+    writeln!(entry, "=== {} sha256:{digest}", timestamp()).unwrap();
+    diff::write_to(&mut entry, &last, &current).unwrap();
+    timeline.write_all(&entry).map_err(|_| Error::Io)?;
+    fs::write(dir.join("last"), text).map_err(|_| Error::Io)
+}
@@ -0,0 +1,144 @@
+// SPDX-License-Identifier: MIT
+//! Newline-delimited JSON-RPC 2.0 over a Unix domain socket.
+//!
+//! Access control is left to the socket's file mode: whoever can connect can
+//! read, and can also write unless the server was started read-only. Values
+//! are exchanged as hex strings so binary data survives the trip.
+
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    os::unix::{
+        fs::{FileTypeExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    sync::Arc,
+    thread,
+};
+
+use nix::sys::stat::{umask, Mode};
+use serde_json::{json, Value};
+
+use crate::{daemon::Store, from_hex, to_hex, Error};
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+struct Server {
+    store: Store,
+    read_only: bool,
+}
+
+impl Server {
+    fn call(&self, method: &str, params: &Value) -> std::result::Result<Value, (i64, String)> {
+        let name = || {
+            params["name"]
+                .as_str()
+                .ok_or((INVALID_PARAMS, "missing name".to_owned()))
+        };
+        let failed = |e: Error| (SERVER_ERROR, format!("{e}"));
+        if self.read_only && matches!(method, "set" | "delete") {
+            return Err((SERVER_ERROR, "server is read-only".to_owned()));
+        }
+        match method {
+            "list" => Ok(json!(self.store.list().map_err(failed)?)),
+            "get" => Ok(json!({ "hex": to_hex(&self.store.get(name()?).map_err(failed)?) })),
+            "set" => {
+                let value = params["hex"]
+                    .as_str()
+                    .and_then(from_hex)
+                    .ok_or((INVALID_PARAMS, "missing or invalid hex".to_owned()))?;
+                self.store.set(name()?, &value).map_err(failed)?;
+                Ok(Value::Null)
+            }
+            "delete" => {
+                self.store.delete(name()?).map_err(failed)?;
+                Ok(Value::Null)
+            }
+            _ => Err((METHOD_NOT_FOUND, format!("unknown method {method}"))),
+        }
+    }
+
+    fn handle(&self, line: &str) -> Value {
+        let req: Value = match serde_json::from_str(line) {
+            Ok(v) => v,
+            Err(e) => {
+                return json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": { "code": PARSE_ERROR, "message": e.to_string() },
+                })
+            }
+        };
+        let id = req["id"].clone();
+        match self.call(req["method"].as_str().unwrap_or(""), &req["params"]) {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+        }
+    }
+
+    fn client(&self, stream: UnixStream) {
+        let mut writer = match stream.try_clone() {
+            Ok(w) => w,
+            Err(_) => return,
+        };
+        for line in BufReader::new(stream).lines() {
+            let line = match line {
+                Ok(l) => l,
+                Err(_) => return,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let reply = self.handle(&line);
+            if writeln!(writer, "{reply}").is_err() {
+                return;
+            }
+        }
+    }
+}
+
+pub fn serve(device: &str, path: &str, mode: u32, read_only: bool) -> std::io::Result<()> {
+    match fs::symlink_metadata(path) {
+        // Only a socket nobody listens on any more is left over to remove.
+        Ok(meta) if meta.file_type().is_socket() => match UnixStream::connect(path) {
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    "is being served already",
+                ))
+            }
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => fs::remove_file(path)?,
+            Err(e) => return Err(e),
+        },
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "exists and is not a socket",
+            ))
+        }
+        Err(_) => {}
+    }
+    // Nobody gets to connect before the socket has its final mode.
+    let old = umask(Mode::from_bits_truncate(0o077));
+    let listener = UnixListener::bind(path);
+    umask(old);
+    let listener = listener?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    let server = Arc::new(Server {
+        store: Store::new(device),
+        read_only,
+    });
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let server = server.clone();
+        thread::spawn(move || server.client(stream));
+    }
+    Ok(())
+}
@@ -0,0 +1,72 @@
+// SPDX-License-Identifier: MIT
+//! Rhai scripts for bulk edits.
+//!
+//! The script sees a map `nvram` from `section:name` to the value in the
+//! `%xx` escaped form that `read` prints, except that `%` itself is written
+//! `%25` so every value comes back unchanged. Whatever it adds, changes or
+//! removes in the map is written back as a single new generation.
+//!
+//! ```rhai
+//! let args = nvram["common:boot-args"];
+//! args.replace("debug=0x14e", "debug=0x104");
+//! nvram["common:boot-args"] = args;
+//! ```
+
+use std::path::Path;
+
+use rhai::{Dynamic, Engine, Map, Scope};
+
+use crate::{diff::Snapshot, logging, read_var, section_name, staging::escape, Error, Result};
+
+pub fn run(path: &Path, before: &Snapshot) -> Result<Snapshot> {
+    let mut map = Map::new();
+    for ((section, key), value) in before {
+        let name = format!("{section}:{}", String::from_utf8_lossy(key));
+        map.insert(name.into(), Dynamic::from(escape(value)));
+    }
+    let mut scope = Scope::new();
+    scope.push("nvram", map);
+    Engine::new()
+        .run_file_with_scope(&mut scope, path.to_path_buf())
+        .map_err(|e| {
+            logging::error(&format!("{}: {e}", path.display()));
+            Error::Script
+        })?;
+    let map = scope.get_value::<Map>("nvram").ok_or(Error::Script)?;
+    let mut after = Snapshot::new();
+    for (name, value) in map {
+        let (section, key) = name.split_once(':').ok_or(Error::MissingPartitionName)?;
+        let value = value.into_string().map_err(|_| {
+            logging::error(&format!("{name} is not set to a string"));
+            Error::Script
+        })?;
+        after.insert(
+            (section_name(section)?, key.as_bytes().to_vec()),
+            read_var(&value)?,
+        );
+    }
+    Ok(after)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_script_changes_nothing() {
+        let path = std::env::temp_dir().join(format!("nvram-script-{}.rhai", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut before = Snapshot::new();
+        for (key, value) in [
+            ("percent", &b"%ab"[..]),
+            ("trailing", b"100%"),
+            ("binary", &[0, 0xff, b'%', 0x7f, b'\n']),
+            ("empty", b""),
+        ] {
+            before.insert(("system", key.as_bytes().to_vec()), value.to_vec());
+        }
+        let after = run(&path, &before);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(after.unwrap(), before);
+    }
+}
@@ -0,0 +1,259 @@
+// SPDX-License-Identifier: MIT
+//! Line-oriented shell for poking at variables.
+//!
+//! `set` and `delete` are only staged; `commit` hands them back to the
+//! caller, which writes all of them as a single new generation.
+
+use std::path::PathBuf;
+
+use rustyline::{
+    completion::Completer, error::ReadlineError, highlight::Highlighter, hint::Hinter,
+    history::FileHistory, validate::Validator, Context, Editor, Helper,
+};
+
+use crate::{
+    config::Config,
+    diff::{self, Changes, Snapshot},
+    format_value, glob_match, logging, read_var, section_name, Error, Result,
+};
+
+// (command, arguments, what it does)
+const COMMANDS: &[(&str, &str, &str)] = &[
+    (
+        "get",
+        "NAME...",
+        "print variables, including staged changes",
+    ),
+    (
+        "list",
+        "[PATTERN]",
+        "list variable names, optionally matching a glob",
+    ),
+    ("set", "NAME=VALUE", "stage a new value, %xx for raw bytes"),
+    ("delete", "NAME...", "stage deleting variables"),
+    ("status", "", "show staged changes"),
+    (
+        "revert",
+        "[NAME...]",
+        "drop staged changes, all of them by default",
+    ),
+    ("commit", "", "write staged changes"),
+    ("quit", "", "leave, asking first if changes are staged"),
+    ("help", "", "show this list"),
+];
+
+struct Names(Vec<String>);
+
+impl Completer for Names {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let line = &line[..pos];
+        let start = line.rfind(' ').map_or(0, |i| i + 1);
+        let word = &line[start..];
+        let candidates = if start == 0 {
+            COMMANDS
+                .iter()
+                .map(|(c, _, _)| *c)
+                .filter(|c| c.starts_with(word))
+                .map(|c| format!("{c} "))
+                .collect()
+        } else {
+            self.0
+                .iter()
+                .filter(|n| n.starts_with(word))
+                .cloned()
+                .collect()
+        };
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for Names {
+    type Hint = String;
+}
+impl Highlighter for Names {}
+impl Validator for Names {}
+impl Helper for Names {}
+
+pub struct Shell<'a> {
+    editor: Editor<Names, FileHistory>,
+    config: &'a Config,
+    history: Option<PathBuf>,
+    staged: Changes,
+}
+
+enum Step {
+    Continue,
+    Quit,
+    Commit,
+}
+
+impl Shell<'_> {
+    pub fn new(config: &Config) -> Result<Shell<'_>> {
+        let mut editor = Editor::new().map_err(|_| Error::Io)?;
+        let history = config.history_path();
+        if let Some(path) = &history {
+            // No history yet is fine.
+            let _ = editor.load_history(path);
+        }
+        Ok(Shell {
+            editor,
+            config,
+            history,
+            staged: Changes::new(),
+        })
+    }
+
+    fn save_history(&mut self) {
+        let Some(path) = &self.history else {
+            return;
+        };
+        if let Some(dir) = path.parent() {
+            let _ = std::fs::create_dir_all(dir);
+        }
+        if let Err(e) = self.editor.save_history(path) {
+            logging::warn(&format!(
+                "could not save history to {}: {e}",
+                path.display()
+            ));
+        }
+    }
+
+    /// Reads commands until the user commits or quits. `names` are offered
+    /// for completion. Returns the changes to write, or `None` on quit.
+    pub fn run(&mut self, current: &Snapshot, names: Vec<String>) -> Result<Option<Changes>> {
+        self.editor.set_helper(Some(Names(names)));
+        let mut quit_armed = false;
+        loop {
+            let line = match self.editor.readline("nvram> ") {
+                Ok(line) => line,
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => "quit".to_owned(),
+                Err(_) => return Err(Error::Io),
+            };
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let _ = self.editor.add_history_entry(line);
+            let (command, rest) = line.split_once(' ').unwrap_or((line, ""));
+            if command != "quit" && command != "exit" {
+                quit_armed = false;
+            }
+            match self.command(command, rest.trim(), current) {
+                Ok(Step::Continue) => {}
+                Ok(Step::Commit) => {
+                    self.save_history();
+                    return Ok(Some(std::mem::take(&mut self.staged)));
+                }
+                Ok(Step::Quit) if self.staged.is_empty() || quit_armed => {
+                    self.save_history();
+                    return Ok(None);
+                }
+                Ok(Step::Quit) => {
+                    quit_armed = true;
+                    println!(
+                        "{} staged changes, quit again to discard them",
+                        self.staged.len()
+                    );
+                }
+                Err(e) => println!("error: {e}"),
+            }
+        }
+    }
+
+    // The value `name` will have after committing.
+    fn value<'a>(&'a self, current: &'a Snapshot, name: &str) -> Result<Option<&'a [u8]>> {
+        if let Some(staged) = self.staged.get(name) {
+            return Ok(staged.as_deref());
+        }
+        let (section, key) = name.split_once(':').ok_or(Error::MissingPartitionName)?;
+        let section = section_name(section)?;
+        Ok(current
+            .get(&(section, key.as_bytes().to_vec()))
+            .map(|v| &v[..]))
+    }
+
+    fn pending(&self, current: &Snapshot) -> Snapshot {
+        diff::apply(current, &self.staged)
+    }
+
+    fn command(&mut self, command: &str, args: &str, current: &Snapshot) -> Result<Step> {
+        let config = self.config;
+        match command {
+            "get" => {
+                for name in args.split_whitespace() {
+                    let name = config.expand(name);
+                    match self.value(current, name)? {
+                        Some(v) => println!("{name}={}", format_value(v)),
+                        None => println!("{name}: (not set)"),
+                    }
+                }
+            }
+            "list" | "ls" => {
+                for ((section, key), _) in self.pending(current) {
+                    let name = format!("{section}:{}", String::from_utf8_lossy(&key));
+                    if args.is_empty() || glob_match(args, &name) {
+                        println!("{name}");
+                    }
+                }
+            }
+            "set" => {
+                let (name, value) = args.split_once('=').ok_or(Error::MissingValue)?;
+                let name = config.expand(name.trim());
+                let (section, key) = name.split_once(':').ok_or(Error::MissingPartitionName)?;
+                let key = (section_name(section)?, key.as_bytes().to_vec());
+                let value = read_var(value)?;
+                if current.get(&key) == Some(&value) {
+                    self.staged.remove(name);
+                } else {
+                    self.staged.insert(name.to_owned(), Some(value));
+                }
+            }
+            "delete" | "rm" => {
+                for name in args.split_whitespace() {
+                    let name = config.expand(name);
+                    if self.value(current, name)?.is_none() {
+                        println!("{name}: (not set)");
+                        continue;
+                    }
+                    self.staged.insert(name.to_owned(), None);
+                }
+            }
+            "status" => {
+                if diff::print(current, &self.pending(current)) == 0 {
+                    println!("nothing staged");
+                }
+            }
+            "revert" => {
+                if args.is_empty() {
+                    self.staged.clear();
+                }
+                for name in args.split_whitespace() {
+                    self.staged.remove(config.expand(name));
+                }
+            }
+            "commit" => {
+                if self.staged.is_empty() {
+                    println!("nothing to commit");
+                } else {
+                    return Ok(Step::Commit);
+                }
+            }
+            "quit" | "exit" => return Ok(Step::Quit),
+            "help" | "?" => {
+                for (command, args, help) in COMMANDS {
+                    println!("{:22} {help}", format!("{command} {args}"));
+                }
+            }
+            _ => println!("unknown command {command}, try help"),
+        }
+        Ok(Step::Continue)
+    }
+}
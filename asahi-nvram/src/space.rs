@@ -0,0 +1,122 @@
+// SPDX-License-Identifier: MIT
+//! Free-space thresholds from the `[free-space]` config table, in percent of
+//! a section in use, checked before every write.
+//!
+//! Growing a section past `warn` prints a notice, past `fail` it needs
+//! `--force`. Writes that don't grow a section are always let through, so an
+//! overfull store can still be cleaned up.
+//!
+//! A section that would not fit at all is reported with the variables that
+//! grew it, whatever the thresholds.
+
+use std::sync::OnceLock;
+
+use apple_nvram::{Nvram, Section, Variable};
+
+use crate::{logging, Error, Result};
+
+#[derive(Default, Clone, Copy)]
+pub struct Thresholds {
+    pub warn: Option<u64>,
+    pub fail: Option<u64>,
+}
+
+static LIMITS: OnceLock<(Thresholds, bool)> = OnceLock::new();
+
+pub fn set(thresholds: Thresholds, force: bool) {
+    let _ = LIMITS.set((thresholds, force));
+}
+
+fn entry_bytes(var: &Variable) -> usize {
+    var.key.len() + var.value.len() + 2
+}
+
+// Variables of `new` that are new or bigger than in `old`, most grown first.
+fn growth<'a>(old: &Section, new: &'a Section) -> Vec<(&'a [u8], usize)> {
+    let mut ret = new
+        .values
+        .values()
+        .filter_map(|var| {
+            let before = old.values.get(var.key).map_or(0, entry_bytes);
+            let grew = entry_bytes(var).checked_sub(before)?;
+            (grew > 0).then_some((var.key, grew))
+        })
+        .collect::<Vec<_>>();
+    ret.sort_by_key(|&(_, grew)| std::cmp::Reverse(grew));
+    ret
+}
+
+fn overflow(name: &str, old: &Section, new: &Section) -> Result<()> {
+    let (used, size) = (new.used_bytes(), new.size_bytes());
+    if used <= size {
+        return Ok(());
+    }
+    logging::error(&format!(
+        "{name} would be {} bytes over its size ({used} of {size} bytes)",
+        used - size
+    ));
+    for (key, grew) in growth(old, new).into_iter().take(5) {
+        logging::error(&format!(
+            "  {name}:{} takes {grew} more bytes",
+            String::from_utf8_lossy(key)
+        ));
+    }
+    Err(Error::SectionTooBig)
+}
+
+// Expects the previous state in the inactive bank, like write_nvram().
+pub fn check(nv: &Nvram) -> Result<()> {
+    let (old, new) = (&nv.partitions[1 - nv.active], &nv.partitions[nv.active]);
+    let sections: [(&str, &Section, &Section); 2] = [
+        ("common", &old.common, &new.common),
+        ("system", &old.system, &new.system),
+    ];
+    for (name, old, new) in sections {
+        overflow(name, old, new)?;
+    }
+    let Some(&(limits, force)) = LIMITS.get() else {
+        return Ok(());
+    };
+    for (name, old, new) in sections {
+        let used = new.used_bytes();
+        if used <= old.used_bytes() {
+            continue;
+        }
+        let size = new.size_bytes();
+        let percent = (used * 100 / size) as u64;
+        let message = format!("{name} would be {percent}% full ({used} of {size} bytes)");
+        if limits.fail.is_some_and(|fail| percent >= fail) {
+            if !force {
+                logging::error(&format!("{message}, use --force to write anyway"));
+                return Err(Error::SectionTooFull);
+            }
+            logging::warn(&message);
+        } else if limits.warn.is_some_and(|warn| percent >= warn) {
+            logging::warn(&message);
+        }
+    }
+    Ok(())
+}
+
+/// Prints how full each section is and its `top` biggest variables.
+pub fn report(nv: &Nvram, top: usize) {
+    let part = &nv.partitions[nv.active];
+    for (name, section) in [("common", &part.common), ("system", &part.system)] {
+        let (used, size) = (section.used_bytes(), section.size_bytes());
+        println!(
+            "{name}: {used} of {size} bytes used ({}%), {} free, {} variables",
+            used * 100 / size.max(1),
+            size.saturating_sub(used),
+            section.values.len()
+        );
+        let mut vars = section.values.values().collect::<Vec<_>>();
+        vars.sort_by_key(|var| std::cmp::Reverse(entry_bytes(var)));
+        for var in vars.into_iter().take(top) {
+            println!(
+                "  {:>6}  {name}:{}",
+                entry_bytes(var),
+                String::from_utf8_lossy(var.key)
+            );
+        }
+    }
+}
@@ -0,0 +1,82 @@
+// SPDX-License-Identifier: MIT
+//! Changes collected by `stage` until `commit` writes them in one go.
+//!
+//! Kept as a JSON object from `section:name` to the `%xx` escaped value, or
+//! `null` for a pending delete.
+
+use std::path::PathBuf;
+
+use serde_json::{Map, Value};
+
+use crate::{diff::Changes, logging, read_var, section_name, Error, Result};
+
+pub struct Staging {
+    path: Option<PathBuf>,
+    pub changes: Changes,
+}
+
+/// Like format_value(), but also escapes `%` so that read_var() gives back
+/// exactly the same bytes.
+pub fn escape(value: &[u8]) -> String {
+    let mut ret = String::new();
+    for &c in value {
+        if c.is_ascii() && !c.is_ascii_control() && c != b'%' {
+            ret.push(c as char);
+        } else {
+            ret.push_str(&format!("%{c:02x}"));
+        }
+    }
+    ret
+}
+
+impl Staging {
+    pub fn load(path: Option<PathBuf>) -> Result<Staging> {
+        let mut changes = Changes::new();
+        let text = match path.as_ref().map(std::fs::read_to_string) {
+            Some(Ok(text)) => text,
+            _ => return Ok(Staging { path, changes }),
+        };
+        let object = match serde_json::from_str::<Map<String, Value>>(&text) {
+            Ok(object) => object,
+            Err(e) => {
+                logging::error(&format!("{}: {e}", path.as_ref().unwrap().display()));
+                return Err(Error::Parse);
+            }
+        };
+        for (name, value) in object {
+            let (section, _) = name.split_once(':').ok_or(Error::MissingPartitionName)?;
+            section_name(section)?;
+            let value = match value {
+                Value::String(s) => Some(read_var(&s)?),
+                Value::Null => None,
+                _ => return Err(Error::Parse),
+            };
+            changes.insert(name, value);
+        }
+        Ok(Staging { path, changes })
+    }
+
+    /// Saves the staged changes, removing the file once there are none left.
+    pub fn save(&self) -> Result<()> {
+        let path = self.path.as_ref().ok_or(Error::Io)?;
+        if self.changes.is_empty() {
+            return match std::fs::remove_file(path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(Error::Io),
+                _ => Ok(()),
+            };
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|_| Error::Io)?;
+        }
+        let object = self
+            .changes
+            .iter()
+            .map(|(name, value)| {
+                let value = value.as_deref().map_or(Value::Null, |v| escape(v).into());
+                (name.clone(), value)
+            })
+            .collect::<Map<_, _>>();
+        let text = serde_json::to_string_pretty(&object).unwrap();
+        std::fs::write(path, text + "\n").map_err(|_| Error::Io)
+    }
+}
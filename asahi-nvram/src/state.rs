@@ -0,0 +1,41 @@
+// SPDX-License-Identifier: MIT
+//! Desired-state files: one TOML table per section, values in the same
+//! `%xx` escaped form that `write` accepts. A variable listed twice is a
+//! parse error, so a file can never ask for two different values.
+//!
+//! ```toml
+//! [common]
+//! boot-args = "debug=0x14e"
+//! ```
+
+use crate::{logging, read_var, Error, Result};
+
+pub struct Desired {
+    pub section: String,
+    pub key: String,
+    pub value: Vec<u8>,
+}
+
+pub fn load(path: &str) -> Result<Vec<Desired>> {
+    let text = std::fs::read_to_string(path).map_err(|_| Error::Io)?;
+    let table = text.parse::<toml::Table>().map_err(|e| {
+        logging::error(&format!("{path}: {e}"));
+        Error::Parse
+    })?;
+    let mut ret = Vec::new();
+    for (section, vars) in table {
+        if section != "common" && section != "system" {
+            return Err(Error::UnknownPartition);
+        }
+        let vars = vars.as_table().ok_or(Error::Parse)?;
+        for (key, value) in vars {
+            let value = value.as_str().ok_or(Error::Parse)?;
+            ret.push(Desired {
+                section: section.clone(),
+                key: key.clone(),
+                value: read_var(value)?,
+            });
+        }
+    }
+    Ok(ret)
+}
@@ -0,0 +1,60 @@
+// SPDX-License-Identifier: MIT
+//! "Did you mean" hints for mistyped partition and variable names.
+
+use apple_nvram::Nvram;
+
+use crate::{all_names, logging, Error};
+
+fn distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut prev = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let cur = row[j + 1];
+            row[j + 1] = if ca == cb {
+                prev
+            } else {
+                1 + prev.min(cur).min(row[j])
+            };
+            prev = cur;
+        }
+    }
+    row[b.len()]
+}
+
+/// Up to three candidates that `name` is plausibly a typo of, closest first.
+pub fn closest<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let key = name.split_once(':').map_or(name, |(_, k)| k);
+    let max = (name.chars().count() / 4).max(2);
+    let mut found = candidates
+        .into_iter()
+        .filter(|&c| c != name)
+        .map(|c| {
+            // The right name in the wrong partition is as good as a one-letter typo.
+            let same_key = c.split_once(':').is_some_and(|(_, k)| k == key);
+            (if same_key { 1 } else { distance(name, c) }, c)
+        })
+        .filter(|&(d, _)| d <= max)
+        .collect::<Vec<_>>();
+    found.sort();
+    found.into_iter().take(3).map(|(_, c)| c).collect()
+}
+
+pub fn hint<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) {
+    let found = closest(name, candidates);
+    if !found.is_empty() {
+        logging::warn(&format!(
+            "{name} not found, did you mean {}?",
+            found.join(" or ")
+        ));
+    }
+}
+
+/// Logs suggestions for a variable that isn't set and returns the error for it.
+pub fn not_found(nv: &Nvram, name: &str) -> Error {
+    let names = all_names(Some(nv));
+    hint(name, names.iter().map(|n| n.as_str()));
+    Error::VariableNotFound
+}
@@ -0,0 +1,268 @@
+// SPDX-License-Identifier: MIT
+//! Interactive browser and editor for nvram variables.
+//!
+//! Edits are only staged in memory; committing hands them back to the caller,
+//! which writes all of them as a single new generation.
+
+use std::io;
+
+use ratatui::{
+    crossterm::event::{self, Event, KeyCode, KeyEventKind},
+    layout::{Constraint, Layout},
+    style::{Modifier, Style},
+    text::Line,
+    widgets::{Block, List, ListItem, ListState, Paragraph, Wrap},
+    DefaultTerminal, Frame,
+};
+
+use crate::{
+    diff::{Changes, Snapshot},
+    format_value, from_hex, read_var, staging, to_hex,
+};
+
+const HELP: &str = "j/k move  x hex  e edit  d delete  u undo  c commit  q quit";
+
+struct App {
+    vars: Vec<(String, Vec<u8>)>,
+    staged: Changes,
+    list: ListState,
+    hex: bool,
+    input: Option<String>,
+    status: String,
+    quit_armed: bool,
+}
+
+enum Step {
+    Continue,
+    Quit,
+    Commit,
+}
+
+impl App {
+    fn new(snapshot: &Snapshot) -> App {
+        let vars = snapshot
+            .iter()
+            .map(|((section, key), value)| {
+                (
+                    format!("{section}:{}", String::from_utf8_lossy(key)),
+                    value.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut list = ListState::default();
+        if !vars.is_empty() {
+            list.select(Some(0));
+        }
+        App {
+            vars,
+            staged: Changes::new(),
+            list,
+            hex: false,
+            input: None,
+            status: String::new(),
+            quit_armed: false,
+        }
+    }
+
+    fn selected(&self) -> Option<&(String, Vec<u8>)> {
+        self.vars.get(self.list.selected()?)
+    }
+
+    // The value as it will be after committing, `None` if staged for deletion.
+    fn value<'a>(&'a self, name: &str, original: &'a [u8]) -> Option<&'a [u8]> {
+        match self.staged.get(name) {
+            Some(staged) => staged.as_deref(),
+            None => Some(original),
+        }
+    }
+
+    fn render_value(&self, value: &[u8]) -> String {
+        if !self.hex {
+            return format_value(value);
+        }
+        value
+            .chunks(16)
+            .enumerate()
+            .map(|(i, chunk)| {
+                let bytes = chunk
+                    .iter()
+                    .map(|b| format!("{b:02x}"))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                format!("{:04x}: {bytes}", i * 16)
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [main, status] =
+            Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+        let [names, value] =
+            Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+                .areas(main);
+
+        let items = self
+            .vars
+            .iter()
+            .map(|(name, _)| {
+                let mark = match self.staged.get(name) {
+                    Some(Some(_)) => '*',
+                    Some(None) => '-',
+                    None => ' ',
+                };
+                ListItem::new(format!("{mark} {name}"))
+            })
+            .collect::<Vec<_>>();
+        let title = format!("variables ({} staged)", self.staged.len());
+        let list = List::new(items)
+            .block(Block::bordered().title(title))
+            .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, names, &mut self.list);
+
+        let (title, text) = match self.selected() {
+            Some((name, original)) => match self.value(name, original) {
+                Some(v) => (format!("{name} ({} bytes)", v.len()), self.render_value(v)),
+                None => (name.clone(), "(staged for deletion)".to_owned()),
+            },
+            None => (String::new(), String::new()),
+        };
+        let view = if self.hex { "hex" } else { "string" };
+        let block = Block::bordered().title(format!("{title} [{view}]"));
+        frame.render_widget(
+            Paragraph::new(text).block(block).wrap(Wrap { trim: false }),
+            value,
+        );
+
+        let line = match &self.input {
+            Some(input) => format!("{view}> {input}"),
+            None if !self.status.is_empty() => self.status.clone(),
+            None => HELP.to_owned(),
+        };
+        frame.render_widget(Line::raw(line), status);
+    }
+
+    fn edit(&mut self) {
+        let Some((name, original)) = self.selected() else {
+            return;
+        };
+        let value = self.value(name, original).unwrap_or(original);
+        self.input = Some(if self.hex {
+            to_hex(value)
+        } else {
+            // Unlike the display, % has to be escaped to read back the same.
+            staging::escape(value)
+        });
+    }
+
+    fn finish_edit(&mut self, input: &str) {
+        let parsed = if self.hex {
+            let digits = input.split_whitespace().collect::<String>();
+            from_hex(&digits).ok_or(())
+        } else {
+            read_var(input).map_err(|_| ())
+        };
+        let Ok(value) = parsed else {
+            self.status = "invalid value, edit discarded".to_owned();
+            return;
+        };
+        let (name, original) = self.selected().unwrap().clone();
+        if value == original {
+            self.staged.remove(&name);
+        } else {
+            self.staged.insert(name, Some(value));
+        }
+    }
+
+    fn key(&mut self, code: KeyCode) -> Step {
+        if let Some(mut input) = self.input.take() {
+            match code {
+                KeyCode::Enter => self.finish_edit(&input),
+                KeyCode::Esc => {}
+                KeyCode::Backspace => {
+                    input.pop();
+                    self.input = Some(input);
+                }
+                KeyCode::Char(c) => {
+                    input.push(c);
+                    self.input = Some(input);
+                }
+                _ => self.input = Some(input),
+            }
+            return Step::Continue;
+        }
+        self.status.clear();
+        if !matches!(code, KeyCode::Char('q') | KeyCode::Esc) {
+            self.quit_armed = false;
+        }
+        match code {
+            KeyCode::Up | KeyCode::Char('k') => self.list.select_previous(),
+            KeyCode::Down | KeyCode::Char('j') => self.list.select_next(),
+            KeyCode::Home | KeyCode::Char('g') => self.list.select_first(),
+            KeyCode::End | KeyCode::Char('G') => self.list.select_last(),
+            KeyCode::Char('x') | KeyCode::Tab => self.hex = !self.hex,
+            KeyCode::Enter | KeyCode::Char('e') => self.edit(),
+            KeyCode::Char('d') => {
+                if let Some((name, _)) = self.selected() {
+                    let name = name.clone();
+                    if self.staged.get(&name) == Some(&None) {
+                        self.staged.remove(&name);
+                    } else {
+                        self.staged.insert(name, None);
+                    }
+                }
+            }
+            KeyCode::Char('u') => {
+                if let Some((name, _)) = self.selected() {
+                    let name = name.clone();
+                    self.staged.remove(&name);
+                }
+            }
+            KeyCode::Char('c') => {
+                if self.staged.is_empty() {
+                    self.status = "nothing to commit".to_owned();
+                } else {
+                    return Step::Commit;
+                }
+            }
+            KeyCode::Char('q') | KeyCode::Esc => {
+                if self.staged.is_empty() || self.quit_armed {
+                    return Step::Quit;
+                }
+                self.quit_armed = true;
+                self.status = format!(
+                    "{} staged changes, press q again to discard them",
+                    self.staged.len()
+                );
+            }
+            _ => {}
+        }
+        Step::Continue
+    }
+
+    fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<Option<Changes>> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match self.key(key.code) {
+                Step::Continue => {}
+                Step::Quit => return Ok(None),
+                Step::Commit => return Ok(Some(std::mem::take(&mut self.staged))),
+            }
+        }
+    }
+}
+
+/// Runs the editor until the user quits, returning the changes to commit, if any.
+pub fn run(snapshot: &Snapshot) -> io::Result<Option<Changes>> {
+    let mut app = App::new(snapshot);
+    let mut terminal = ratatui::init();
+    let ret = app.run(&mut terminal);
+    ratatui::restore();
+    ret
+}
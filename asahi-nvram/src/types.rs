@@ -0,0 +1,163 @@
+// SPDX-License-Identifier: MIT
+//! `--type`: values that hold a number, a flag or a UUID rather than text.
+//! Integers are little-endian like everything else the firmware stores.
+//! `utf16` strings are NUL-terminated, as EFI writes them.
+
+use apple_nvram::Variable;
+
+use crate::{devpath, from_hex, to_hex};
+
+pub const TYPES: [&str; 10] = [
+    "string", "hex", "bool", "u8", "u16", "u32", "u64", "uuid", "utf16", "devpath",
+];
+
+fn int<const N: usize>(raw: &[u8]) -> Option<u64> {
+    let bytes: [u8; N] = raw.try_into().ok()?;
+    let mut padded = [0; 8];
+    padded[..N].copy_from_slice(&bytes);
+    Some(u64::from_le_bytes(padded))
+}
+
+fn parse_int(text: &str, bytes: usize) -> Option<Vec<u8>> {
+    let n = match text.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None => text.parse().ok()?,
+    };
+    if bytes < 8 && n >> (bytes * 8) != 0 {
+        return None;
+    }
+    Some(n.to_le_bytes()[..bytes].to_vec())
+}
+
+/// The value of `var` shown as `ty`, or `None` if it does not have the right
+/// form.
+pub fn decode(ty: &str, var: &Variable) -> Option<String> {
+    let raw = &var.bytes()[..];
+    Some(match ty {
+        "string" => var.as_str()?,
+        "hex" => to_hex(raw),
+        "bool" => var.as_bool()?.to_string(),
+        "u8" => int::<1>(raw)?.to_string(),
+        "u16" => int::<2>(raw)?.to_string(),
+        "u32" => var.as_u32_le()?.to_string(),
+        "u64" => int::<8>(raw)?.to_string(),
+        "uuid" => {
+            let hex = to_hex(<&[u8; 16]>::try_from(raw).ok()?);
+            format!(
+                "{}-{}-{}-{}-{}",
+                &hex[..8],
+                &hex[8..12],
+                &hex[12..16],
+                &hex[16..20],
+                &hex[20..]
+            )
+            .to_ascii_uppercase()
+        }
+        "utf16" => {
+            let mut units = raw
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .collect::<Vec<_>>();
+            if !raw.len().is_multiple_of(2) {
+                return None;
+            }
+            while units.last() == Some(&0) {
+                units.pop();
+            }
+            String::from_utf16(&units).ok()?
+        }
+        "devpath" => devpath::decode(raw)?,
+        _ => return None,
+    })
+}
+
+/// The bytes for `text` read as `ty`, or `None` if it is not one.
+pub fn encode(ty: &str, text: &str) -> Option<Vec<u8>> {
+    match ty {
+        "string" => Some(text.as_bytes().to_vec()),
+        "hex" => from_hex(text),
+        // The firmware's own flags are the strings, not a byte.
+        "bool" => match text {
+            "true" | "false" => Some(text.as_bytes().to_vec()),
+            _ => None,
+        },
+        "u8" => parse_int(text, 1),
+        "u16" => parse_int(text, 2),
+        "u32" => parse_int(text, 4),
+        "u64" => parse_int(text, 8),
+        "uuid" => {
+            let hex = text.replace('-', "");
+            (hex.len() == 32).then(|| from_hex(&hex))?
+        }
+        "utf16" => Some(
+            text.encode_utf16()
+                .chain([0])
+                .flat_map(u16::to_le_bytes)
+                .collect(),
+        ),
+        "devpath" => devpath::encode(text),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(ty: &str, raw: &[u8]) -> Option<String> {
+        super::decode(ty, &Variable::from_bytes(b"test", raw))
+    }
+
+    #[test]
+    fn integers_are_little_endian() {
+        assert_eq!(
+            encode("u32", "0x12345678").unwrap(),
+            [0x78, 0x56, 0x34, 0x12]
+        );
+        assert_eq!(
+            decode("u32", &[0x78, 0x56, 0x34, 0x12]).unwrap(),
+            "305419896"
+        );
+        assert_eq!(decode("u16", &[1, 1]).unwrap(), "257");
+        assert_eq!(encode("u64", "1").unwrap(), [1, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn integers_must_fit() {
+        assert!(encode("u8", "256").is_none());
+        assert!(encode("u16", "0x10000").is_none());
+        assert!(encode("u32", "-1").is_none());
+        assert!(decode("u32", &[1, 2, 3]).is_none());
+        assert!(decode("u16", &[1, 2, 3]).is_none());
+    }
+
+    #[test]
+    fn round_trips() {
+        for (ty, text) in [
+            ("string", "hello"),
+            ("hex", "00ff10"),
+            ("bool", "true"),
+            ("u8", "7"),
+            ("uuid", "0123ABCD-4567-89EF-0123-456789ABCDEF"),
+            ("utf16", "Macintosh HD"),
+            ("devpath", "Pci(0x1c,0x0)"),
+        ] {
+            assert_eq!(
+                decode(ty, &encode(ty, text).unwrap()).unwrap(),
+                text,
+                "{ty}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_the_wrong_form() {
+        assert!(decode("string", &[0xff]).is_none());
+        assert!(decode("bool", b"yes").is_none());
+        assert_eq!(decode("bool", &[1]).unwrap(), "true");
+        assert!(decode("uuid", &[0; 15]).is_none());
+        assert!(encode("uuid", "0123").is_none());
+        assert!(decode("utf16", &[b'a', 0, b'b']).is_none());
+        assert!(decode("nope", b"").is_none());
+    }
+}
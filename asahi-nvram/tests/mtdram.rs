@@ -0,0 +1,124 @@
+// SPDX-License-Identifier: MIT
+//! End-to-end runs against a real MTD device, backed by the mtdram module.
+//!
+//! These load and unload kernel modules, so they only build with the
+//! `mtd-tests` feature and are skipped unless running as root:
+//!
+//! ```sh
+//! sudo cargo test -p asahi-nvram --features mtd-tests --test mtdram
+//! ```
+#![cfg(feature = "mtd-tests")]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+const NAME: &str = "mtdram test device";
+
+/// An mtdram device the size of a full store, removed again on drop.
+struct MtdRam {
+    device: PathBuf,
+    scratch: PathBuf,
+}
+
+impl MtdRam {
+    fn new() -> Option<MtdRam> {
+        let status = fs::read_to_string("/proc/self/status").unwrap();
+        if !status.lines().any(|l| l.starts_with("Uid:\t0\t")) {
+            eprintln!("skipping, mtdram tests need root");
+            return None;
+        }
+        // Sizes are in KiB: one 128 KiB store, erased in 64 KiB blocks like a bank.
+        let ok = Command::new("modprobe")
+            .args(["mtdram", "total_size=128", "erase_size=64"])
+            .status()
+            .expect("could not run modprobe")
+            .success();
+        assert!(ok, "modprobe mtdram failed");
+        let mtd = fs::read_to_string("/proc/mtd").unwrap();
+        let index = mtd
+            .lines()
+            .find(|l| l.ends_with(&format!("\"{NAME}\"")))
+            .and_then(|l| l.split(':').next()?.strip_prefix("mtd"))
+            .expect("no mtdram device in /proc/mtd")
+            .to_owned();
+        let scratch = std::env::temp_dir().join(format!("asahi-nvram-mtd-{}", std::process::id()));
+        fs::create_dir_all(&scratch).unwrap();
+        Some(MtdRam {
+            device: PathBuf::from(format!("/dev/mtd{index}")),
+            scratch,
+        })
+    }
+
+    fn run(&self, args: &[&str]) -> Output {
+        let out = Command::new(env!("CARGO_BIN_EXE_asahi-nvram"))
+            .arg("-d")
+            .arg(&self.device)
+            .args(args)
+            // Keep the user's config, hooks and labels out of the way.
+            .env("XDG_CONFIG_HOME", &self.scratch)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "{args:?}: {out:?}");
+        out
+    }
+
+    fn read(&self, name: &str) -> String {
+        String::from_utf8(self.run(&["read", name]).stdout).unwrap()
+    }
+
+    // Formats the device with an empty store, made from an empty directory.
+    fn format(&self) {
+        let empty = self.scratch.join("empty");
+        fs::create_dir_all(&empty).unwrap();
+        let image = self.scratch.join("empty.img");
+        let out = Command::new(env!("CARGO_BIN_EXE_asahi-nvram"))
+            .arg("-d")
+            .arg(format!("dir:{}", empty.display()))
+            .args(["dump", "-o"])
+            .arg(&image)
+            .output()
+            .unwrap();
+        assert!(out.status.success(), "dump: {out:?}");
+        // Freshly loaded mtdram is erased, so a plain write is fine here.
+        fs::write(&self.device, fs::read(&image).unwrap()).unwrap();
+    }
+}
+
+impl Drop for MtdRam {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.scratch);
+        let _ = Command::new("modprobe").args(["-r", "mtdram"]).status();
+    }
+}
+
+fn generation(dev: &Path) -> Vec<u32> {
+    let data = fs::read(dev).unwrap();
+    let nv = apple_nvram::Nvram::parse(&data).unwrap();
+    nv.partitions.iter().map(|p| p.generation).collect()
+}
+
+#[test]
+fn erase_write_read_cycle() {
+    let Some(mtd) = MtdRam::new() else {
+        return;
+    };
+    mtd.format();
+    assert_eq!(generation(&mtd.device), [1, 1]);
+
+    mtd.run(&["write", "common:test-var=first"]);
+    assert_eq!(mtd.read("common:test-var"), "common:test-var=first\n");
+
+    // Overwriting the bank written first needs it to be erased.
+    mtd.run(&["write", "common:test-var=second", "system:other=x"]);
+    mtd.run(&["write", "common:test-var=third"]);
+    assert_eq!(mtd.read("common:test-var"), "common:test-var=third\n");
+    assert_eq!(mtd.read("system:other"), "system:other=x\n");
+    assert_eq!(generation(&mtd.device), [4, 3]);
+
+    mtd.run(&["delete", "system:other"]);
+    let all = String::from_utf8(mtd.run(&["read"]).stdout).unwrap();
+    assert_eq!(all, "common:test-var=third\n");
+}
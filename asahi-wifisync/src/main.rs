@@ -3,12 +3,13 @@
 use std::{
     env,
     fmt::Debug,
-    fs::OpenOptions,
-    io::{self, Read},
+    fs,
+    io::{self, stdout, Write},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
     path::Path,
 };
 
-use apple_nvram::{Nvram, UnescapeVal, Variable};
+use apple_nvram::{store, Nvram, Variable};
 
 use ini::Ini;
 
@@ -19,6 +20,9 @@ enum Error {
     VariableNotFound,
     FileIO,
     IWDConfigDirNotFound,
+    ConfigDirNotFound,
+    SecretsHidden,
+    UnknownFormat,
 }
 
 impl From<apple_nvram::Error> for Error {
@@ -26,6 +30,8 @@ impl From<apple_nvram::Error> for Error {
         match e {
             apple_nvram::Error::ParseError => Error::Parse,
             apple_nvram::Error::SectionTooBig => Error::SectionTooBig,
+            apple_nvram::Error::UnsupportedVersion(_) => Error::Parse,
+            apple_nvram::Error::IoError(_) => Error::FileIO,
         }
     }
 }
@@ -52,18 +58,31 @@ fn real_main() -> Result<()> {
                 .arg(clap::arg!(-c --config [CONFIG] "IWD config path."))
                 .arg(clap::Arg::new("variable").multiple_values(true)),
         )
+        .subcommand(
+            clap::Command::new("export")
+                .about("Export wlan information from nvram as connection profiles")
+                .arg(
+                    clap::arg!(-f --format [FORMAT] "Output format.").value_parser([
+                        "networkmanager",
+                        "iwd",
+                        "wpa_supplicant",
+                    ]),
+                )
+                .arg(clap::arg!(--"show-secrets" "Include pre-shared keys in the output."))
+                .arg(clap::arg!(--install "Write the profiles where they are looked for."))
+                .arg(
+                    clap::arg!(-c --config [CONFIG] "Directory to install into.")
+                        .requires("install"),
+                ),
+        )
         .get_matches();
 
     let default_name = "/dev/mtd0ro".to_owned();
     let default_config = "/var/lib/iwd".to_owned();
     let wlan_var = "preferred-networks";
 
-    let mut file = OpenOptions::new()
-        .read(true)
-        .open(matches.get_one::<String>("device").unwrap_or(&default_name))
-        .unwrap();
-    let mut data = Vec::new();
-    file.read_to_end(&mut data).unwrap();
+    let device = matches.get_one::<String>("device").unwrap_or(&default_name);
+    let data = store::open(device, false)?.read()?;
     let mut nv = Nvram::parse(&data)?;
     let wlan_devs = nv
         .active_part_mut()
@@ -83,6 +102,29 @@ fn real_main() -> Result<()> {
             )
             .expect("Failed to sync wlan device info");
         }
+        Some(("export", args)) => {
+            let format = args
+                .get_one::<String>("format")
+                .map(|s| s.as_str())
+                .unwrap_or("networkmanager");
+            if args.contains_id("install") {
+                let dir = match format {
+                    "networkmanager" => "/etc/NetworkManager/system-connections",
+                    "iwd" => default_config.as_str(),
+                    _ => {
+                        eprintln!("{format} profiles cannot be installed");
+                        return Err(Error::UnknownFormat);
+                    }
+                };
+                install_wlankeys(
+                    wlan_devs,
+                    format,
+                    args.get_one::<String>("config").map_or(dir, |s| s.as_str()),
+                )?;
+            } else {
+                export_wlankeys(wlan_devs, format, args.contains_id("show-secrets"))?;
+            }
+        }
         _ => {
             print_wlankeys(wlan_devs).expect("Failed to parse wlan device info");
         }
@@ -99,7 +141,7 @@ const CHUNK_LEN: usize = 0xc0;
 
 fn parse_wlan_info(var: &Variable) -> Vec<Network> {
     let mut nets = Vec::new();
-    let data = UnescapeVal::new(var.value.iter().copied()).collect::<Vec<_>>();
+    let data = var.bytes();
     for chunk in data.chunks(CHUNK_LEN) {
         let ssid_len = u32::from_le_bytes(chunk[0xc..0x10].try_into().unwrap()) as usize;
         let ssid = String::from_utf8_lossy(&chunk[0x10..0x10 + ssid_len]).to_string();
@@ -167,3 +209,114 @@ fn sync_wlankeys(var: &Variable, config: &String) -> Result<()> {
     }
     Ok(())
 }
+
+// iwd names files after the SSID as is when it is made of these, after its
+// hex prefixed with = otherwise.
+fn iwd_name(ssid: &str) -> String {
+    if ssid
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || " -_".contains(c))
+    {
+        ssid.to_owned()
+    } else {
+        format!("={}", format_psk(ssid.as_bytes()))
+    }
+}
+
+// wpa_supplicant reads a quoted SSID up to its last quote, so only plain
+// printable ASCII without quotes goes in as is, anything else as bare hex.
+fn wpa_ssid(ssid: &str) -> String {
+    if ssid.chars().all(|c| (' '..='~').contains(&c) && c != '"') {
+        format!("\"{ssid}\"")
+    } else {
+        format_psk(ssid.as_bytes())
+    }
+}
+
+// File name and contents of the profile for `net` in `format`.
+fn profile(net: &Network, format: &str) -> Result<(String, Vec<u8>)> {
+    let mut info = Ini::new();
+    let name = match format {
+        "networkmanager" => {
+            info.with_section(Some("connection"))
+                .set("id", net.ssid.clone())
+                .set("type", "wifi");
+            info.with_section(Some("wifi"))
+                .set("mode", "infrastructure")
+                .set("ssid", net.ssid.clone());
+            if let Some(psk) = &net.psk {
+                info.with_section(Some("wifi-security"))
+                    .set("key-mgmt", "wpa-psk")
+                    .set("psk", format_psk(psk));
+            }
+            info.with_section(Some("ipv4")).set("method", "auto");
+            info.with_section(Some("ipv6")).set("method", "auto");
+            format!("{}.nmconnection", net.ssid.replace('/', "_"))
+        }
+        "iwd" => match &net.psk {
+            Some(psk) => {
+                info.with_section(Some("Security"))
+                    .set("PreSharedKey", format_psk(psk));
+                format!("{}.psk", iwd_name(&net.ssid))
+            }
+            None => format!("{}.open", iwd_name(&net.ssid)),
+        },
+        "wpa_supplicant" => {
+            let body = match &net.psk {
+                Some(psk) => format!("\tpsk={}", format_psk(psk)),
+                None => "\tkey_mgmt=NONE".to_owned(),
+            };
+            let block = format!("network={{\n\tssid={}\n{body}\n}}\n", wpa_ssid(&net.ssid));
+            return Ok((String::new(), block.into_bytes()));
+        }
+        _ => return Err(Error::UnknownFormat),
+    };
+    let mut data = Vec::new();
+    info.write_to(&mut data)?;
+    Ok((name, data))
+}
+
+fn export_wlankeys(var: &Variable, format: &str, show_secrets: bool) -> Result<()> {
+    let nets = parse_wlan_info(var);
+    if !show_secrets && nets.iter().any(|n| n.psk.is_some()) {
+        eprintln!("refusing to print pre-shared keys without --show-secrets");
+        return Err(Error::SecretsHidden);
+    }
+
+    for net in nets {
+        let (name, data) = profile(&net, format)?;
+        if !name.is_empty() {
+            println!("# {name}");
+        }
+        stdout().write_all(&data)?;
+        if !name.is_empty() {
+            println!();
+        }
+    }
+    Ok(())
+}
+
+// Writes the profiles into `dir`, readable by root only as both
+// NetworkManager and iwd require for files holding keys.
+fn install_wlankeys(var: &Variable, format: &str, dir: &str) -> Result<()> {
+    let dir = Path::new(dir);
+    if !dir.is_dir() {
+        eprintln!("{} does not exist", dir.display());
+        return Err(Error::ConfigDirNotFound);
+    }
+    for net in parse_wlan_info(var) {
+        let (name, data) = profile(&net, format)?;
+        let path = dir.join(name);
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)?;
+        // The mode only applies to new files.
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+        file.write_all(&data)?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}